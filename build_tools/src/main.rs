@@ -3,7 +3,7 @@ use std::{path::PathBuf, process::Command};
 fn main() {
     let mut args = std::env::args().skip(1).peekable(); // skip executable name
 
-    let mut raw_binaries = Vec::new();
+    let mut binaries = Vec::new();
     let mut run_vm = false;
     let mut release = false;
     let mut use_miri = false;
@@ -23,8 +23,7 @@ fn main() {
                     for arg in next.split(',') {
                         let arg = arg.trim();
                         build_vm_binary(arg);
-                        let path = create_raw_binary(arg);
-                        raw_binaries.push(path);
+                        binaries.push(vm_binary_path(arg));
                     }
                 }
                 args.next();
@@ -53,7 +52,7 @@ fn main() {
         }
 
         let mut str = String::new();
-        for p in raw_binaries {
+        for p in binaries {
             if !str.is_empty() {
                 str.push(',');
             }
@@ -99,27 +98,11 @@ pub fn build_vm_binary(name: &str) {
     let _ = run_cmd.status().unwrap();
 }
 
-pub fn create_raw_binary(name: &str) -> PathBuf {
-    let llvm_tools = llvm_tools::LlvmTools::new().unwrap();
-    let objcopy = llvm_tools.tool(&llvm_tools::exe("llvm-objcopy")).unwrap();
-
-    let mut run_cmd = Command::new(objcopy);
+pub fn vm_binary_path(name: &str) -> PathBuf {
     let mut path = std::env::current_dir().unwrap();
     path.push("target");
     path.push("mips");
     path.push("release");
-    run_cmd.current_dir(path.clone());
-
-    run_cmd
-        .arg("-O")
-        .arg("binary")
-        .arg("-I")
-        .arg("elf32-big")
-        .arg(&format!("./{}", name))
-        .arg(&format!("./{}.bin", name));
-
-    let _ = run_cmd.status().unwrap();
-
-    path.push(&format!("{}.bin", name));
+    path.push(name);
     path
 }
@@ -1,6 +1,176 @@
 #![no_std]
-extern crate alloc;
-pub use emballoc::*;
 
-// pub mod alloc;
-// pub use alloc::*;
+use core::{
+    alloc::{GlobalAlloc, Layout},
+    mem::size_of,
+    ptr::{null_mut, NonNull},
+};
+
+use rlib::{memory::map_memory, sync::Mutex};
+
+extern "C" {
+    /// End of the program image, defined by the linker script
+    static _heap: u8;
+}
+
+/// Every block handed out or kept in the free list is aligned to and a multiple of this, which
+/// is also exactly the size of a [`FreeBlock`]
+const BLOCK_ALIGN: usize = 2 * size_of::<usize>();
+/// Smallest amount of memory requested from the system at once, mapped pages only cost memory
+/// once they are used so this can be generous
+const MIN_GROWTH: usize = 0x100000;
+
+/// A free block of memory, stored at the start of the block itself
+struct FreeBlock {
+    size: usize,
+    next: Option<NonNull<FreeBlock>>,
+}
+
+struct Heap {
+    /// Free blocks sorted by address, neighbouring blocks are always merged
+    free: Option<NonNull<FreeBlock>>,
+    /// Where the next mapping should go so it continues the last one
+    end: usize,
+}
+
+unsafe impl Send for Heap {}
+
+impl Heap {
+    const fn new() -> Self {
+        Self { free: None, end: 0 }
+    }
+
+    unsafe fn alloc(&mut self, layout: Layout) -> *mut u8 {
+        let size = block_size(layout.size());
+        let align = layout.align().max(BLOCK_ALIGN);
+
+        if let Some(ptr) = self.take(size, align) {
+            return ptr;
+        }
+        if !self.grow(size + align) {
+            return null_mut();
+        }
+        self.take(size, align).unwrap_or(null_mut())
+    }
+
+    unsafe fn dealloc(&mut self, ptr: *mut u8, layout: Layout) {
+        self.insert(ptr as usize, block_size(layout.size()));
+    }
+
+    /// Removes the first fitting block from the free list, giving back what is left on either
+    /// side of the allocation
+    unsafe fn take(&mut self, size: usize, align: usize) -> Option<*mut u8> {
+        let mut prev: Option<NonNull<FreeBlock>> = None;
+        let mut current = self.free;
+        while let Some(block) = current {
+            let block_start = block.as_ptr() as usize;
+            let block_size = block.as_ref().size;
+            let next = block.as_ref().next;
+
+            let start = align_up(block_start, align);
+            if start + size <= block_start + block_size {
+                match prev {
+                    Some(mut prev) => prev.as_mut().next = next,
+                    None => self.free = next,
+                }
+                // both leftovers are multiples of BLOCK_ALIGN so nothing is lost
+                let front = start - block_start;
+                let back = block_start + block_size - start - size;
+                if front != 0 {
+                    self.insert(block_start, front);
+                }
+                if back != 0 {
+                    self.insert(start + size, back);
+                }
+                return Some(start as *mut u8);
+            }
+
+            prev = current;
+            current = next;
+        }
+        None
+    }
+
+    /// Adds a block to the free list, merging it with its neighbours
+    unsafe fn insert(&mut self, start: usize, size: usize) {
+        let mut prev: Option<NonNull<FreeBlock>> = None;
+        let mut next = self.free;
+        while let Some(block) = next {
+            if block.as_ptr() as usize > start {
+                break;
+            }
+            prev = next;
+            next = block.as_ref().next;
+        }
+
+        let mut block = NonNull::new_unchecked(start as *mut FreeBlock);
+        block.as_ptr().write(FreeBlock { size, next });
+
+        if let Some(next) = next {
+            if start + size == next.as_ptr() as usize {
+                block.as_mut().size += next.as_ref().size;
+                block.as_mut().next = next.as_ref().next;
+            }
+        }
+
+        match prev {
+            Some(mut prev) if prev.as_ptr() as usize + prev.as_ref().size == start => {
+                prev.as_mut().size += block.as_ref().size;
+                prev.as_mut().next = block.as_ref().next;
+            }
+            Some(mut prev) => prev.as_mut().next = Some(block),
+            None => self.free = Some(block),
+        }
+    }
+
+    /// Maps at least `size` more bytes, right after the previous mapping if possible
+    unsafe fn grow(&mut self, size: usize) -> bool {
+        if self.end == 0 {
+            self.end = align_up(core::ptr::addr_of!(_heap) as usize, BLOCK_ALIGN);
+        }
+        let Some((start, len)) = map_memory(self.end as *mut u8, size.max(MIN_GROWTH)) else {
+            return false;
+        };
+        let start = start.as_ptr() as usize;
+        self.end = start + len;
+        self.insert(start, len);
+        true
+    }
+}
+
+fn align_up(val: usize, align: usize) -> usize {
+    (val + align - 1) & !(align - 1)
+}
+
+fn block_size(size: usize) -> usize {
+    align_up(size.max(1), BLOCK_ALIGN)
+}
+
+/// A global allocator that asks the system for more pages whenever it runs out of memory
+pub struct Allocator {
+    heap: Mutex<Heap>,
+}
+
+impl Allocator {
+    pub const fn new() -> Self {
+        Self {
+            heap: Mutex::new(Heap::new()),
+        }
+    }
+}
+
+impl Default for Allocator {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+unsafe impl GlobalAlloc for Allocator {
+    unsafe fn alloc(&self, layout: Layout) -> *mut u8 {
+        self.heap.lock().alloc(layout)
+    }
+
+    unsafe fn dealloc(&self, ptr: *mut u8, layout: Layout) {
+        self.heap.lock().dealloc(ptr, layout)
+    }
+}
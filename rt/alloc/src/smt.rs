@@ -1,12 +0,0 @@
-pub struct Allocator{
-    
-}
-
-impl Allocator{
-    pub fn jkad(&mut self){
-        
-    }
-}
-
-
-
@@ -1,8 +1,8 @@
 //Basic mips stuff
 
-/// Does not take reguments nor return anything
+/// Exits the calling thread, the rest of the process keeps running
 ///
-/// Halts the VM
+/// Register 4: exit code
 pub const HALT: u32 = 0;
 
 /// Print a 2's complement i32 to standard output
@@ -20,6 +20,26 @@ pub const PRINT_C_STRING: u32 = 4;
 /// Register 4: the char to print
 pub const PRINT_CHAR: u32 = 5;
 
+/// Exits every thread in the calling process
+///
+/// Register 4: exit code
+pub const EXIT_PROCESS: u32 = 10;
+
+/// Clone the calling process, the new process starts out as a copy of the calling thread and
+/// shares its memory copy on write
+///
+/// Register 2: Id of the new process in the caller, 0 in the new process
+pub const CLONE_PROCESS: u32 = 11;
+
+/// Block until another process ends, only one waiter gets its exit code
+///
+/// Register 4: Id of the process to wait for
+///
+/// Register 2: Exit code of the process
+/// Register 3: 0 if the process exited, 1 if it was killed by a fault, 2 if there is no such
+/// process to wait for
+pub const PROCESS_WAIT: u32 = 12;
+
 /// Sleep for x ms
 ///
 /// Register 4: the number of ms to sleep for
@@ -48,8 +68,10 @@ pub const GENERATE_THREAD_RANDOM_NUMBER: u32 = 99;
 ///
 /// Register 4: Pointer to thread entry
 /// Register 5: Pointer to thread arguments
+/// Register 6: Size of the threads stack in bytes, rounded up to whole pages (0 for the default)
 ///
-/// Register 2: Non zero Id of created thread (if zero an error occured)
+/// Register 2: Non zero Id of created thread (if zero an error occured, like the process being
+/// at its thread limit)
 pub const START_NEW_THREAD: u32 = 100;
 
 /// Sleep nanoseconds
@@ -60,6 +82,21 @@ pub const SLEEP_NANOS: u32 = 101;
 /// Basically stop exicuting until the scheduler decides this task should run next
 pub const WAIT_CONTINUE: u32 = 102;
 
+/// Block until a thread of the calling process exits
+///
+/// Register 4: Id of the thread to join
+///
+/// Register 2: Exit code of the thread
+/// Register 3: 0 if the thread exited, 2 if the process has no such thread to join
+pub const THREAD_JOIN: u32 = 103;
+
+/// Stop keeping the exit code of a thread of the calling process for a join
+///
+/// Register 4: Id of the thread, which may be the calling thread
+///
+/// Register 2: 1 if the thread was detached, 0 if the process has no such thread to join
+pub const THREAD_DETACH: u32 = 104;
+
 /// Wake n tasks for the futex
 ///
 /// Register 4: Pointer to the futex
@@ -75,3 +112,48 @@ pub const FUTEX_WAKE: u32 = 200;
 ///
 /// Register 2: 1 if the condition was met, 0 otherwise
 pub const FUTEX_WAIT: u32 = 201;
+
+/// Map zeroed memory into the process, pages only take up memory once they are first touched
+///
+/// Register 4: Address the mapping should start at, rounded up to a page (0 for anywhere)
+/// Register 5: Length of the mapping in bytes
+///
+/// Register 2: Start of the mapping, 0 if there was no room for it or the process may not use
+/// that many pages
+/// Register 3: Length of the mapping rounded up to whole pages
+pub const MAP_MEMORY: u32 = 300;
+
+/// Unmap memory from the process
+///
+/// Register 4: Start of the range, rounded down to a page
+/// Register 5: Length of the range in bytes
+///
+/// Register 2: Number of pages that were unmapped
+pub const UNMAP_MEMORY: u32 = 301;
+
+/// Open a named shared memory object, creating it if it doesn't exist yet
+///
+/// Register 4: Pointer to the \0 terminated name
+/// Register 5: Length in bytes the object is created with
+///
+/// Register 2: Id of the object, 0 if it doesn't exist and the length is 0 or the process may not
+/// open another object
+/// Register 3: Length of the object rounded up to whole pages
+pub const OPEN_SHARED_MEMORY: u32 = 310;
+
+/// Map a shared memory object into the process, it is unmapped with [`UNMAP_MEMORY`]
+///
+/// Register 4: Id of the object, the process must have opened it
+/// Register 5: Address the mapping should start at, rounded up to a page (0 for anywhere)
+///
+/// Register 2: Start of the mapping, 0 if it failed
+/// Register 3: Length of the mapping
+pub const MAP_SHARED_MEMORY: u32 = 311;
+
+/// Close the handle of the process to a shared memory object, existing mappings of it stay valid.
+/// The object goes away once every process that opened it closed it or exited
+///
+/// Register 4: Id of the object, the process must have opened it
+///
+/// Register 2: 1 if the handle was closed, 0 if the process has no handle to the object
+pub const CLOSE_SHARED_MEMORY: u32 = 312;
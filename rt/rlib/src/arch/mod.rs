@@ -9,7 +9,7 @@ pub use syscalls::*;
 #[inline(always)]
 pub fn halt() -> ! {
     unsafe {
-        syscall_v_v::<HALT>();
+        syscall_s_v::<HALT>(0);
     }
 
     unsafe {
@@ -21,7 +21,7 @@ pub fn halt() -> ! {
 pub fn halt_fs() -> ! {
     loop {
         unsafe {
-            syscall_v_v::<HALT>();
+            syscall_s_v::<HALT>(0);
         }
     }
 }
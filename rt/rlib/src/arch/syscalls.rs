@@ -224,3 +224,22 @@ pub unsafe fn syscall_ss_s<const CALL_ID: u32>(arg1: u32, arg2: u32) -> u32 {
     );
     ret1
 }
+
+/// # Safety
+///
+/// If you have to read this then you shouldnt be using this. This is a raw System Call, using it
+/// incorrectly can break pretty much anything.
+#[inline(always)]
+pub unsafe fn syscall_ss_ss<const CALL_ID: u32>(arg1: u32, arg2: u32) -> (u32, u32) {
+    let ret1;
+    let ret2;
+    asm!(
+        "syscall {0}",
+        const(CALL_ID),
+        in("$4") arg1,
+        in("$5") arg2,
+        out("$2") ret1,
+        out("$3") ret2,
+    );
+    (ret1, ret2)
+}
@@ -19,6 +19,7 @@ pub mod arch;
 pub mod core_rust;
 pub mod hint;
 pub mod io;
+pub mod memory;
 pub mod process;
 pub mod sync;
 pub mod thread;
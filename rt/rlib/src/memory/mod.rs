@@ -0,0 +1,69 @@
+use core::{ffi::CStr, ptr::NonNull};
+
+/// Maps at least `len` bytes of zeroed memory, preferably starting at `hint`
+///
+/// The system places the mapping somewhere else if `hint` is taken. Returns the start of the
+/// mapping and its actual length, which is rounded up to whole pages. Pages are only backed by
+/// memory once they are first touched, so large mappings are cheap
+pub fn map_memory(hint: *mut u8, len: usize) -> Option<(NonNull<u8>, usize)> {
+    let (start, len) = unsafe {
+        crate::arch::syscall_ss_ss::<{ crate::arch::MAP_MEMORY }>(hint as u32, len as u32)
+    };
+    Some((NonNull::new(start as *mut u8)?, len as usize))
+}
+
+/// Unmaps every page overlapping `len` bytes starting at `start`, returning how many pages were
+/// unmapped
+///
+/// # Safety
+///
+/// Nothing may reference the unmapped memory anymore
+pub unsafe fn unmap_memory(start: *mut u8, len: usize) -> usize {
+    crate::arch::syscall_ss_s::<{ crate::arch::UNMAP_MEMORY }>(start as u32, len as u32) as usize
+}
+
+/// A named block of zeroed memory that every process opening the same name can map
+#[derive(Debug)]
+pub struct SharedMemory {
+    id: u32,
+    len: usize,
+}
+
+impl SharedMemory {
+    /// Opens the shared memory called `name`, creating it with at least `len` bytes if no process
+    /// has done so yet. An existing object keeps its length, which may differ from `len`
+    pub fn open(name: &CStr, len: usize) -> Option<Self> {
+        let (id, len) = unsafe {
+            crate::arch::syscall_ss_ss::<{ crate::arch::OPEN_SHARED_MEMORY }>(
+                name.as_ptr().addr() as u32,
+                len as u32,
+            )
+        };
+        (id != 0).then_some(Self {
+            id,
+            len: len as usize,
+        })
+    }
+
+    /// Size of the object in bytes, always a whole number of pages
+    pub fn size(&self) -> usize {
+        self.len
+    }
+
+    /// Maps the whole object into this process, preferably starting at `hint`. The mapping stays
+    /// until it is removed with [`unmap_memory`]
+    pub fn map(&self, hint: *mut u8) -> Option<NonNull<u8>> {
+        let (start, _) = unsafe {
+            crate::arch::syscall_ss_ss::<{ crate::arch::MAP_SHARED_MEMORY }>(self.id, hint as u32)
+        };
+        NonNull::new(start as *mut u8)
+    }
+
+    /// Closes this handle, mappings of the object stay valid. The name is removed once every
+    /// process that opened the object closed it
+    pub fn close(self) -> bool {
+        unsafe {
+            crate::arch::syscall_s_s::<{ crate::arch::CLOSE_SHARED_MEMORY }>(self.id) != 0
+        }
+    }
+}
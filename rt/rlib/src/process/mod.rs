@@ -1,7 +1,42 @@
 pub fn exit(code: i32) -> ! {
     loop {
         unsafe {
-            crate::arch::syscall_s_v::<0>(code as u32);
+            crate::arch::syscall_s_v::<{ crate::arch::EXIT_PROCESS }>(code as u32);
         }
     }
 }
+
+/// Which side of a [`fork`] we are on
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Fork {
+    /// The original process, with the id of the new one
+    Parent(u32),
+    Child,
+}
+
+/// Clones the calling process, only the calling thread continues in the new process which sees
+/// a copy of all the memory of this one
+pub fn fork() -> Fork {
+    match unsafe { crate::arch::syscall_v_s::<{ crate::arch::CLONE_PROCESS }>() } {
+        0 => Fork::Child,
+        pid => Fork::Parent(pid),
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum WaitError {
+    /// The process was killed by a fault
+    Faulted,
+    /// The process doesn't exist, is the caller or was already waited for
+    NoSuchProcess,
+}
+
+/// Blocks until the process `pid` ends and returns its exit code
+pub fn wait(pid: u32) -> Result<u32, WaitError> {
+    let (code, status) = unsafe { crate::arch::syscall_s_ss::<{ crate::arch::PROCESS_WAIT }>(pid) };
+    match status {
+        0 => Ok(code),
+        1 => Err(WaitError::Faulted),
+        _ => Err(WaitError::NoSuchProcess),
+    }
+}
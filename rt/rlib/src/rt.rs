@@ -18,8 +18,10 @@ extern "C" fn _start() -> ! {
             "jal memset",
 
             "jal main",
+            // returning from main exits the whole process
+            "li $a0, 0",
             "1:",
-            "syscall 0",
+            "syscall 10",
             "b 1b",
             options(noreturn),
         }
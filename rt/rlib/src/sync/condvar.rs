@@ -0,0 +1,58 @@
+use core::sync::atomic::Ordering::Relaxed;
+
+use super::futex::Futex;
+use super::mutex::MutexGuard;
+
+/// The futex holds a sequence number that is bumped on every notify, a waiter only sleeps if no
+/// notification happened between it unlocking the mutex and calling into the kernel
+pub struct Condvar {
+    futex: Futex,
+}
+
+impl Condvar {
+    pub const fn new() -> Self {
+        Self {
+            futex: Futex::new(0),
+        }
+    }
+
+    pub fn wait<'a, T: ?Sized>(&self, guard: MutexGuard<'a, T>) -> MutexGuard<'a, T> {
+        let mutex = guard.mutex;
+        let seq = self.futex.load(Relaxed);
+        drop(guard);
+
+        self.futex.wait(seq);
+
+        mutex.lock()
+    }
+
+    pub fn wait_while<'a, T: ?Sized, F>(
+        &self,
+        mut guard: MutexGuard<'a, T>,
+        mut condition: F,
+    ) -> MutexGuard<'a, T>
+    where
+        F: FnMut(&mut T) -> bool,
+    {
+        while condition(&mut *guard) {
+            guard = self.wait(guard);
+        }
+        guard
+    }
+
+    pub fn notify_one(&self) {
+        self.futex.fetch_add(1, Relaxed);
+        self.futex.wake_one();
+    }
+
+    pub fn notify_all(&self) {
+        self.futex.fetch_add(1, Relaxed);
+        self.futex.wake_all();
+    }
+}
+
+impl Default for Condvar {
+    fn default() -> Self {
+        Self::new()
+    }
+}
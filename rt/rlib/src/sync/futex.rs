@@ -1,3 +1,4 @@
+use core::ops::Deref;
 use core::sync::atomic::AtomicU32;
 
 use crate::arch::syscall_ss_s;
@@ -9,19 +10,36 @@ pub struct Futex {
 }
 
 impl Futex {
-    pub fn wake_one(&self) {
-        unsafe {
-            syscall_ss_s::<FUTEX_WAKE>(&self.futex as *const AtomicU32 as u32, 1);
+    pub const fn new(val: u32) -> Self {
+        Self {
+            futex: AtomicU32::new(val),
         }
     }
 
+    /// Wakes up to `count` tasks waiting on this futex, returning how many were actually woken
+    pub fn wake(&self, count: u32) -> u32 {
+        unsafe { syscall_ss_s::<FUTEX_WAKE>(&self.futex as *const AtomicU32 as u32, count) }
+    }
+
+    pub fn wake_one(&self) {
+        self.wake(1);
+    }
+
     pub fn wake_all(&self) {
-        unsafe {
-            syscall_ss_s::<FUTEX_WAKE>(&self.futex as *const AtomicU32 as u32, u32::MAX);
-        }
+        self.wake(u32::MAX);
     }
 
+    /// Sleeps until woken if the futex still holds `expected`. Returns false without sleeping if
+    /// the value had already changed
     pub fn wait(&self, expected: u32) -> bool {
         unsafe { syscall_ss_s::<FUTEX_WAIT>(&self.futex as *const AtomicU32 as u32, expected) != 0 }
     }
 }
+
+impl Deref for Futex {
+    type Target = AtomicU32;
+
+    fn deref(&self) -> &Self::Target {
+        &self.futex
+    }
+}
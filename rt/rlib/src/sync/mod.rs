@@ -1,12 +1,30 @@
 pub mod futex;
 
+mod barrier;
+mod condvar;
+mod mutex;
+mod once;
+mod rwlock;
+
+pub use barrier::*;
+pub use condvar::*;
+pub use mutex::*;
+pub use once::*;
+pub use rwlock::*;
+
 use spin::RelaxStrategy;
 
-pub type Mutex<T> = spin::mutex::Mutex<T, VmRelax>;
-pub type RwLock<T> = spin::rwlock::RwLock<T, VmRelax>;
 pub type Lazy<T> = spin::Lazy<T, VmRelax>;
-pub type Once<T> = spin::Once<T>;
-pub type Barrier = spin::barrier::Barrier<VmRelax>;
+
+/// Busy waiting locks for when a critical section is too short to be worth sleeping on
+pub mod spin_lock {
+    use super::VmRelax;
+
+    pub type Mutex<T> = spin::mutex::Mutex<T, VmRelax>;
+    pub type RwLock<T> = spin::rwlock::RwLock<T, VmRelax>;
+    pub type Once<T> = spin::Once<T>;
+    pub type Barrier = spin::barrier::Barrier<VmRelax>;
+}
 
 pub struct VmRelax;
 
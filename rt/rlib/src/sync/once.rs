@@ -0,0 +1,68 @@
+use core::sync::atomic::Ordering::{Acquire, Relaxed, Release};
+
+use super::futex::Futex;
+
+const INCOMPLETE: u32 = 0;
+const RUNNING: u32 = 1;
+/// Running and at least one other task is sleeping until it completes
+const QUEUED: u32 = 2;
+const COMPLETE: u32 = 3;
+
+pub struct Once {
+    state: Futex,
+}
+
+impl Once {
+    pub const fn new() -> Self {
+        Self {
+            state: Futex::new(INCOMPLETE),
+        }
+    }
+
+    pub fn is_completed(&self) -> bool {
+        self.state.load(Acquire) == COMPLETE
+    }
+
+    pub fn call_once<F: FnOnce()>(&self, f: F) {
+        if self.is_completed() {
+            return;
+        }
+        self.call_once_slow(f)
+    }
+
+    #[cold]
+    fn call_once_slow<F: FnOnce()>(&self, f: F) {
+        loop {
+            match self.state.load(Acquire) {
+                COMPLETE => return,
+                INCOMPLETE => {
+                    if self
+                        .state
+                        .compare_exchange(INCOMPLETE, RUNNING, Acquire, Acquire)
+                        .is_ok()
+                    {
+                        f();
+                        if self.state.swap(COMPLETE, Release) == QUEUED {
+                            self.state.wake_all();
+                        }
+                        return;
+                    }
+                }
+                RUNNING => {
+                    let _ = self
+                        .state
+                        .compare_exchange(RUNNING, QUEUED, Relaxed, Relaxed);
+                }
+                _ => {
+                    self.state.wait(QUEUED);
+                }
+            }
+        }
+    }
+}
+
+impl Default for Once {
+    fn default() -> Self {
+        Self::new()
+    }
+}
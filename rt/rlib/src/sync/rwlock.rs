@@ -0,0 +1,178 @@
+use core::cell::UnsafeCell;
+use core::ops::{Deref, DerefMut};
+use core::sync::atomic::Ordering::{Acquire, Relaxed, Release};
+
+use super::futex::Futex;
+
+// state layout: the low 31 bits count readers, or are all set when write locked. The top bit is
+// set when some task is (or may be) sleeping on the futex
+const MASK: u32 = 0x7FFF_FFFF;
+const WRITE_LOCKED: u32 = MASK;
+const MAX_READERS: u32 = MASK - 1;
+const WAITING: u32 = !MASK;
+
+pub struct RwLock<T: ?Sized> {
+    state: Futex,
+    data: UnsafeCell<T>,
+}
+
+unsafe impl<T: ?Sized + Send> Send for RwLock<T> {}
+unsafe impl<T: ?Sized + Send + Sync> Sync for RwLock<T> {}
+
+pub struct RwLockReadGuard<'a, T: ?Sized + 'a> {
+    lock: &'a RwLock<T>,
+}
+
+pub struct RwLockWriteGuard<'a, T: ?Sized + 'a> {
+    lock: &'a RwLock<T>,
+}
+
+unsafe impl<T: ?Sized + Sync> Sync for RwLockReadGuard<'_, T> {}
+unsafe impl<T: ?Sized + Sync> Sync for RwLockWriteGuard<'_, T> {}
+
+impl<T> RwLock<T> {
+    pub const fn new(val: T) -> Self {
+        Self {
+            state: Futex::new(0),
+            data: UnsafeCell::new(val),
+        }
+    }
+
+    pub fn into_inner(self) -> T {
+        self.data.into_inner()
+    }
+}
+
+impl<T: ?Sized> RwLock<T> {
+    pub fn read(&self) -> RwLockReadGuard<'_, T> {
+        loop {
+            let state = self.state.load(Relaxed);
+            if state & MASK < MAX_READERS {
+                if self
+                    .state
+                    .compare_exchange_weak(state, state + 1, Acquire, Relaxed)
+                    .is_ok()
+                {
+                    return RwLockReadGuard { lock: self };
+                }
+            } else {
+                self.wait(state);
+            }
+        }
+    }
+
+    pub fn try_read(&self) -> Option<RwLockReadGuard<'_, T>> {
+        let state = self.state.load(Relaxed);
+        if state & MASK < MAX_READERS {
+            self.state
+                .compare_exchange(state, state + 1, Acquire, Relaxed)
+                .ok()
+                .map(|_| RwLockReadGuard { lock: self })
+        } else {
+            None
+        }
+    }
+
+    pub fn write(&self) -> RwLockWriteGuard<'_, T> {
+        loop {
+            let state = self.state.load(Relaxed);
+            if state & MASK == 0 {
+                if self
+                    .state
+                    .compare_exchange_weak(state, state | WRITE_LOCKED, Acquire, Relaxed)
+                    .is_ok()
+                {
+                    return RwLockWriteGuard { lock: self };
+                }
+            } else {
+                self.wait(state);
+            }
+        }
+    }
+
+    pub fn try_write(&self) -> Option<RwLockWriteGuard<'_, T>> {
+        let state = self.state.load(Relaxed);
+        if state & MASK == 0 {
+            self.state
+                .compare_exchange(state, state | WRITE_LOCKED, Acquire, Relaxed)
+                .ok()
+                .map(|_| RwLockWriteGuard { lock: self })
+        } else {
+            None
+        }
+    }
+
+    pub fn get_mut(&mut self) -> &mut T {
+        self.data.get_mut()
+    }
+
+    fn wait(&self, state: u32) {
+        // announce that we are about to sleep before actually doing so, the futex only puts us to
+        // sleep if nobody unlocked in between
+        if state & WAITING == 0
+            && self
+                .state
+                .compare_exchange(state, state | WAITING, Relaxed, Relaxed)
+                .is_err()
+        {
+            return;
+        }
+        self.state.wait(state | WAITING);
+    }
+
+    fn read_unlock(&self) {
+        let prev = self.state.fetch_sub(1, Release);
+        // if we were the last reader hand the lock to whoever is waiting. If the exchange fails
+        // someone else took the lock and will do the waking when they unlock
+        if prev == WAITING | 1 && self.state.compare_exchange(WAITING, 0, Relaxed, Relaxed).is_ok()
+        {
+            self.state.wake_all();
+        }
+    }
+
+    fn write_unlock(&self) {
+        if self.state.swap(0, Release) & WAITING != 0 {
+            self.state.wake_all();
+        }
+    }
+}
+
+impl<T: Default> Default for RwLock<T> {
+    fn default() -> Self {
+        Self::new(T::default())
+    }
+}
+
+impl<T: ?Sized> Deref for RwLockReadGuard<'_, T> {
+    type Target = T;
+
+    fn deref(&self) -> &T {
+        unsafe { &*self.lock.data.get() }
+    }
+}
+
+impl<T: ?Sized> Drop for RwLockReadGuard<'_, T> {
+    fn drop(&mut self) {
+        self.lock.read_unlock()
+    }
+}
+
+impl<T: ?Sized> Deref for RwLockWriteGuard<'_, T> {
+    type Target = T;
+
+    fn deref(&self) -> &T {
+        unsafe { &*self.lock.data.get() }
+    }
+}
+
+impl<T: ?Sized> DerefMut for RwLockWriteGuard<'_, T> {
+    fn deref_mut(&mut self) -> &mut T {
+        unsafe { &mut *self.lock.data.get() }
+    }
+}
+
+impl<T: ?Sized> Drop for RwLockWriteGuard<'_, T> {
+    fn drop(&mut self) {
+        self.lock.write_unlock()
+    }
+}
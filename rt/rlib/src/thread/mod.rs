@@ -1,15 +1,56 @@
 use core::{fmt::Display, num::NonZeroU32, time::Duration};
 
-use crate::arch::START_NEW_THREAD;
+use crate::arch::{START_NEW_THREAD, THREAD_DETACH, THREAD_JOIN};
 
 #[cfg(feature = "alloc")]
 extern crate alloc;
 
 #[cfg(feature = "alloc")]
-use alloc::boxed::Box;
+use alloc::{boxed::Box, sync::Arc};
 
 #[cfg(feature = "alloc")]
-pub fn spawn<F, T>(f: F) -> Result<ThreadJoinHandle, ()>
+use core::cell::UnsafeCell;
+
+#[cfg(feature = "alloc")]
+pub fn spawn<F, T>(f: F) -> Result<JoinHandle<T>, ()>
+where
+    F: FnOnce() -> T,
+    F: Send + 'static,
+    T: Send + 'static,
+{
+    Builder::new().spawn(f)
+}
+
+/// Configuration for a new thread
+#[derive(Default)]
+pub struct Builder {
+    stack_size: usize,
+}
+
+impl Builder {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Size of the new threads stack in bytes, the system rounds this up to whole pages
+    pub fn stack_size(mut self, size: usize) -> Self {
+        self.stack_size = size;
+        self
+    }
+
+    #[cfg(feature = "alloc")]
+    pub fn spawn<F, T>(self, f: F) -> Result<JoinHandle<T>, ()>
+    where
+        F: FnOnce() -> T,
+        F: Send + 'static,
+        T: Send + 'static,
+    {
+        spawn_with_stack(f, self.stack_size)
+    }
+}
+
+#[cfg(feature = "alloc")]
+fn spawn_with_stack<F, T>(f: F, stack_size: usize) -> Result<JoinHandle<T>, ()>
 where
     F: FnOnce() -> T,
     F: Send + 'static,
@@ -17,21 +58,29 @@ where
 {
     use crate::arch::halt_fs;
 
+    let packet = Arc::new(Packet {
+        result: UnsafeCell::new(None),
+    });
+    let their_packet = packet.clone();
+
     let main = move || {
-        f();
+        let result = f();
+        unsafe {
+            *their_packet.result.get() = Some(result);
+        }
     };
     let main: Box<dyn FnOnce() + 'static + Send> = box main;
     let p = Box::into_raw(box main);
 
     let p = p as *mut core::ffi::c_void;
-    let res = unsafe { create_thread(run_thread, p) };
+    let res = unsafe { create_thread_with_stack(run_thread, p, stack_size) };
     if res.is_err() {
         unsafe {
             //drop if thread isnt created
-            let _ = Box::from_raw(p);
+            let _ = Box::from_raw(p as *mut Box<dyn FnOnce() + Send>);
         }
     }
-    return res;
+    return res.map(|thread| JoinHandle { thread, packet });
 
     extern "C" fn run_thread(main: *mut core::ffi::c_void) -> ! {
         unsafe {
@@ -41,11 +90,61 @@ where
     }
 }
 
+/// Where a spawned thread leaves the value its closure returned
+#[cfg(feature = "alloc")]
+struct Packet<T> {
+    result: UnsafeCell<Option<T>>,
+}
+
+// only the spawned thread writes the result and only after it has exited does the joiner read it
+#[cfg(feature = "alloc")]
+unsafe impl<T: Send> Sync for Packet<T> {}
+
+#[cfg(feature = "alloc")]
+pub struct JoinHandle<T> {
+    thread: ThreadJoinHandle,
+    packet: Arc<Packet<T>>,
+}
+
+#[cfg(feature = "alloc")]
+impl<T> JoinHandle<T> {
+    pub fn id(&self) -> NonZeroU32 {
+        self.thread.id()
+    }
+
+    /// Blocks until the thread exits and returns what its closure returned
+    pub fn join(self) -> Result<T, JoinError> {
+        self.thread.join()?;
+        // the thread has exited so we are the only one left looking at the packet
+        unsafe { (*self.packet.result.get()).take() }.ok_or(JoinError::Killed)
+    }
+}
+
+#[cfg(feature = "alloc")]
+impl<T> Display for JoinHandle<T> {
+    fn fmt(&self, f: &mut core::fmt::Formatter<'_>) -> core::fmt::Result {
+        self.thread.fmt(f)
+    }
+}
+
 pub unsafe fn create_thread(
     main: extern "C" fn(*mut core::ffi::c_void) -> !,
     args: *mut core::ffi::c_void,
 ) -> Result<ThreadJoinHandle, ()> {
-    let res = crate::arch::syscall_ss_s::<START_NEW_THREAD>(main as u32, args as u32);
+    create_thread_with_stack(main, args, 0)
+}
+
+/// Like [`create_thread`] but with a stack of at least `stack_size` bytes, 0 for the default size
+pub unsafe fn create_thread_with_stack(
+    main: extern "C" fn(*mut core::ffi::c_void) -> !,
+    args: *mut core::ffi::c_void,
+    stack_size: usize,
+) -> Result<ThreadJoinHandle, ()> {
+    let res = crate::arch::syscall_sss_s::<START_NEW_THREAD>(
+        main as u32,
+        args as u32,
+        stack_size as u32,
+    );
     if let Some(id) = NonZeroU32::new(res) {
         Ok(ThreadJoinHandle { id })
     } else {
@@ -57,12 +156,56 @@ pub struct ThreadJoinHandle {
     id: NonZeroU32,
 }
 
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum JoinError {
+    /// The thread was killed before it could finish
+    Killed,
+    /// The thread doesn't exist or was already joined
+    NotJoinable,
+}
+
+impl ThreadJoinHandle {
+    pub fn id(&self) -> NonZeroU32 {
+        self.id
+    }
+
+    /// Blocks until the thread exits and returns its exit code
+    pub fn join(self) -> Result<u32, JoinError> {
+        // a joined thread doesn't need detaching
+        let id = core::mem::ManuallyDrop::new(self).id;
+        let (code, status) = unsafe { crate::arch::syscall_s_ss::<THREAD_JOIN>(id.get()) };
+        match status {
+            0 => Ok(code),
+            1 => Err(JoinError::Killed),
+            _ => Err(JoinError::NotJoinable),
+        }
+    }
+}
+
+/// Dropping the handle detaches the thread so its exit code isn't kept around
+impl Drop for ThreadJoinHandle {
+    fn drop(&mut self) {
+        unsafe {
+            crate::arch::syscall_s_s::<THREAD_DETACH>(self.id.get());
+        }
+    }
+}
+
 impl Display for ThreadJoinHandle {
     fn fmt(&self, f: &mut core::fmt::Formatter<'_>) -> core::fmt::Result {
         write!(f, "{}", self.id)
     }
 }
 
+/// Exits only the calling thread, the rest of the process keeps running
+pub fn exit(code: u32) -> ! {
+    loop {
+        unsafe {
+            crate::arch::syscall_s_v::<{ crate::arch::HALT }>(code);
+        }
+    }
+}
+
 pub fn sleep(dur: Duration) {
     let nanos = dur.as_nanos() as u64;
     unsafe {
@@ -8,9 +8,12 @@ use rlib::*;
 
 #[no_mangle]
 fn main() {
+    let mut sleepers = rlib::vec::Vec::new();
     for _i in 0..500 {
         unsafe {
-            _ = rlib::thread::create_thread(start, core::ptr::null_mut());
+            if let Ok(handle) = rlib::thread::create_thread(start, core::ptr::null_mut()) {
+                sleepers.push(handle);
+            }
         }
         extern "C" fn start(_args: *mut core::ffi::c_void) -> ! {
             let tstart = rlib::time::system_time_nanos();
@@ -24,7 +27,7 @@ fn main() {
                     Duration::from_nanos(end - start)
                 );
             }
-            rlib::process::exit(0);
+            rlib::thread::exit(0);
         }
         // let _ = rlib::thread::start_new_thread(move || {
         //     let tstart = rlib::time::systepm_time_nanos();
@@ -46,9 +49,10 @@ fn main() {
 
     let number = 4;
     let handle = rlib::thread::spawn(move || {
+        let mut workers = rlib::vec::Vec::new();
         for i in 0..number {
             println!("NEW THREAD: {}", i);
-            let _ = rlib::thread::spawn(move || {
+            let worker = rlib::thread::spawn(move || {
                 for i in 0..50_000u32 {
                     if is_prime(i) {
                         println!("{i} is prime");
@@ -56,6 +60,10 @@ fn main() {
                 }
             })
             .unwrap();
+            workers.push(worker);
+        }
+        for worker in workers {
+            let _ = worker.join();
         }
     })
     .unwrap();
@@ -64,6 +72,11 @@ fn main() {
     for i in 0..5 {
         println!("Shell: {}", i);
     }
+
+    let _ = handle.join();
+    for sleeper in sleepers {
+        let _ = sleeper.join();
+    }
 }
 
 fn is_prime(n: u32) -> bool {
@@ -79,4 +92,4 @@ fn is_prime(n: u32) -> bool {
 }
 
 #[global_allocator]
-static ALLOCATOR: rt_alloc::Allocator<4096> = rt_alloc::Allocator::new();
+static ALLOCATOR: rt_alloc::Allocator = rt_alloc::Allocator::new();
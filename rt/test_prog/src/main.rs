@@ -19,17 +19,31 @@ fn main() {
     // }
     //rlib::process::exit(0);
 
+    // far bigger than a single page so the heap has to grow a few times
+    let squares: rlib::vec::Vec<u32> = (0..100_000).map(|i| i * i).collect();
+    println!("heap holds {} squares", squares.len());
+    drop(squares);
+
+    let mut workers = rlib::vec::Vec::new();
     for t in 0..10 {
-        rlib::thread::spawn(move || {
+        let handle = rlib::thread::spawn(move || {
+            let mut primes = 0;
             for i in 0..10_000 {
                 //50_000u32 {
                 if is_prime(i) {
                     println!("{t} -> {i} is prime");
+                    primes += 1;
                 }
             }
+            primes
         });
+        workers.push(handle.unwrap());
         println!("{t}");
     }
+
+    for (t, worker) in workers.into_iter().enumerate() {
+        println!("{t} found {:?} primes", worker.join());
+    }
 }
 
 fn is_prime(n: u32) -> bool {
@@ -45,4 +59,4 @@ fn is_prime(n: u32) -> bool {
 }
 
 #[global_allocator]
-static ALLOCATOR: rt_alloc::Allocator<4096> = rt_alloc::Allocator::new();
+static ALLOCATOR: rt_alloc::Allocator = rt_alloc::Allocator::new();
@@ -1,13 +1,27 @@
 use std::{io::Read, time::Instant};
 
-use core::system::System;
+use core::{
+    loader::Program,
+    system::{ProcessExitStatus, ProcessLimits, System},
+    task::DelaySlots,
+    taskpool::FillPolicy,
+};
+
+fn limit_arg<T: std::str::FromStr>(arg: &str, val: Option<String>) -> T {
+    match val.map(|val| val.parse()) {
+        Some(Ok(val)) => val,
+        _ => panic!("Expected a number after {}", arg),
+    }
+}
 
 fn main() {
     tracing_subscriber::fmt::init();
     let mut args = std::env::args().skip(1).peekable(); // skip executable name
 
-    let mut system = System::default();
-    println!("Loading File");
+    let mut builder = System::builder();
+    let mut files = Vec::new();
+    let mut limits = ProcessLimits::UNLIMITED;
+    let mut delay_slots = DelaySlots::Skipped;
 
     while let Some(arg) = args.next() {
         match arg.as_str() {
@@ -16,47 +30,96 @@ fn main() {
                     if next.starts_with('-') {
                         panic!("Expected list of packages to build not: {}", next);
                     }
-                    for arg in next.split(',') {
-                        let arg = arg.trim();
-
-                        let mut file = std::fs::File::open(arg).unwrap();
-                        let mut file_data = Vec::new();
-                        let ammount = file.read_to_end(&mut file_data).unwrap();
-                        if ammount > 0x10000 {
-                            panic!();
-                        }
-
-                        system.add_task_with_pages(&[0x0, 0x7FFF], |pages| {
-                            let page = &*pages.iter().find(|p| p.1 == 0).unwrap().0;
-                            for (index, val) in file_data.iter().enumerate() {
-                                page.set_u8((index % 0x10000) as u16, *val);
-                            }
-                        });
-                    }
+                    files.extend(next.split(',').map(|arg| arg.trim().to_owned()));
                 }
                 args.next();
             }
+            "--fill" => {
+                let fill = match args.next().as_deref() {
+                    Some("zero") => FillPolicy::Zero,
+                    Some("debug") => FillPolicy::DebugPattern,
+                    Some(random) if random == "random" || random.starts_with("random:") => {
+                        FillPolicy::Random(random.strip_prefix("random:").map_or(0, |seed| {
+                            seed.parse().expect("Expected a number as the random seed")
+                        }))
+                    }
+                    other => panic!(
+                        "Expected zero, debug or random[:seed] after --fill not: {:?}",
+                        other
+                    ),
+                };
+                builder = builder.fill_policy(fill);
+            }
+            "--track-uninit" => builder = builder.track_uninitialized_reads(true),
+            "--delay-slots" => delay_slots = DelaySlots::Executed,
+            "--max-pages" => limits.max_pages = Some(limit_arg(&arg, args.next())),
+            "--max-threads" => limits.max_threads = Some(limit_arg(&arg, args.next())),
+            "--max-handles" => limits.max_handles = Some(limit_arg(&arg, args.next())),
+            "--max-instructions" => {
+                limits.max_instructions = Some(limit_arg(&arg, args.next()))
+            }
             _ => {
                 panic!("Invalid arguments given: {}", arg);
             }
         }
     }
 
+    let mut system = builder.build();
+    let mut primary = None;
+    println!("Loading File");
+
+    for file in &files {
+        let mut file_data = Vec::new();
+        std::fs::File::open(file)
+            .unwrap()
+            .read_to_end(&mut file_data)
+            .unwrap();
+
+        match Program::load(&file_data) {
+            Ok(program) => {
+                let tid = system.add_program_with_limits(&program, limits);
+                system.set_delay_slots(tid.to_pid(), delay_slots);
+                primary.get_or_insert(tid.to_pid());
+            }
+            Err(err) => panic!("Failed to load {}: {:?}", file, err),
+        }
+    }
+
     println!("Starting");
 
     let mut system = Box::pin(system);
     // let raw = system.deref_mut() as *mut System;
     // let _ = std::thread::spawn(move || {
     let start = Instant::now();
-    let iters = system.run_blocking();
+    let report = system.run_blocking();
     let dur = start.elapsed();
+    let iters = report.total_iterations;
     println!(
         "All tasks terminated, ran vm for {} iterations in {:?}\nips: {}\nshutting down",
         iters,
         dur,
         iters as f64 / dur.as_secs_f64()
     );
-    std::process::exit(0);
+    for process in &report.processes {
+        println!(
+            "Process: {} {:?} after {} instructions in {:?}",
+            process.pid, process.status, process.instructions, process.wall_time
+        );
+    }
+    if !report.uninitialized_reads.is_empty() {
+        println!(
+            "{} read(s) of uninitialized memory",
+            report.uninitialized_reads.len()
+        );
+    }
+
+    // the exit status of the first program given is the exit status of the vm
+    let code = match primary.and_then(|pid| report.process(pid)).map(|p| &p.status) {
+        None | Some(ProcessExitStatus::Exited(0)) => 0,
+        Some(ProcessExitStatus::Exited(code)) => (*code as u8).max(1) as i32,
+        Some(ProcessExitStatus::Faulted(_)) | Some(ProcessExitStatus::Deadlocked) => 101,
+    };
+    std::process::exit(code);
     // });
 
     // let options = eframe::NativeOptions::default();
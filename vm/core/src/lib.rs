@@ -1,5 +1,6 @@
 #![feature(pointer_byte_offsets)]
 
+pub mod loader;
 pub mod scheduler;
 pub mod system;
 pub mod task;
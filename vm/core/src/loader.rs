@@ -0,0 +1,309 @@
+use crate::task::VmPtr;
+
+pub const ELF_MAGIC: [u8; 4] = [0x7F, b'E', b'L', b'F'];
+
+const ELFCLASS32: u8 = 1;
+const ELFDATA2LSB: u8 = 1;
+const ET_EXEC: u16 = 2;
+const EM_MIPS: u16 = 8;
+const PT_LOAD: u32 = 1;
+const SHT_SYMTAB: u32 = 2;
+
+const PROGRAM_HEADER_SIZE: usize = 32;
+const SECTION_HEADER_SIZE: usize = 40;
+const SYMBOL_SIZE: usize = 16;
+
+/// Default top of the stack used when the program doesn't define `_sp`
+pub const DEFAULT_STACK_POINTER: VmPtr = 0x80000000;
+
+pub const SEGMENT_EXECUTE: u32 = 0b001;
+pub const SEGMENT_WRITE: u32 = 0b010;
+pub const SEGMENT_READ: u32 = 0b100;
+
+#[derive(Debug)]
+pub enum LoadError {
+    NotElf,
+    NotElf32LittleEndian,
+    NotMipsExecutable,
+    /// A header, segment or section points past the end of the file
+    Truncated,
+    /// A segment has a file size larger than its memory size or wraps around the address space
+    MalformedSegment(VmPtr),
+}
+
+#[derive(Debug)]
+pub struct Segment<'a> {
+    pub vaddr: VmPtr,
+    pub mem_size: u32,
+    pub data: &'a [u8],
+    pub flags: u32,
+}
+
+/// A program ready to be placed into memory by [`crate::system::System::add_program`]
+#[derive(Debug)]
+pub struct Program<'a> {
+    pub entry: VmPtr,
+    pub gp: Option<VmPtr>,
+    pub sp: VmPtr,
+    pub segments: Vec<Segment<'a>>,
+}
+
+impl<'a> Program<'a> {
+    /// Loads either an ELF file or a flat binary depending on whether `data` starts with the ELF
+    /// magic
+    pub fn load(data: &'a [u8]) -> Result<Self, LoadError> {
+        if data.starts_with(&ELF_MAGIC) {
+            Self::from_elf(data)
+        } else {
+            Ok(Self::from_raw(data))
+        }
+    }
+
+    /// A flat binary (like the output of objcopy) loaded at address 0 and entered at its start
+    pub fn from_raw(data: &'a [u8]) -> Self {
+        Self {
+            entry: 0,
+            gp: None,
+            sp: DEFAULT_STACK_POINTER,
+            segments: vec![Segment {
+                vaddr: 0,
+                mem_size: data.len() as u32,
+                data,
+                flags: SEGMENT_READ | SEGMENT_WRITE | SEGMENT_EXECUTE,
+            }],
+        }
+    }
+
+    /// Parses an ELF32 little endian MIPS executable
+    pub fn from_elf(data: &'a [u8]) -> Result<Self, LoadError> {
+        if !data.starts_with(&ELF_MAGIC) {
+            return Err(LoadError::NotElf);
+        }
+        if read_u8(data, 4)? != ELFCLASS32 || read_u8(data, 5)? != ELFDATA2LSB {
+            return Err(LoadError::NotElf32LittleEndian);
+        }
+        if read_u16(data, 16)? != ET_EXEC || read_u16(data, 18)? != EM_MIPS {
+            return Err(LoadError::NotMipsExecutable);
+        }
+
+        let entry = read_u32(data, 24)?;
+        let ph_off = read_u32(data, 28)? as usize;
+        let sh_off = read_u32(data, 32)? as usize;
+        let ph_ent_size = read_u16(data, 42)? as usize;
+        let ph_num = read_u16(data, 44)? as usize;
+        let sh_ent_size = read_u16(data, 46)? as usize;
+        let sh_num = read_u16(data, 48)? as usize;
+
+        let mut segments = Vec::new();
+        for i in 0..ph_num {
+            let header = slice(data, ph_off + i * ph_ent_size, PROGRAM_HEADER_SIZE)?;
+            if read_u32(header, 0)? != PT_LOAD {
+                continue;
+            }
+            let offset = read_u32(header, 4)? as usize;
+            let vaddr = read_u32(header, 8)?;
+            let file_size = read_u32(header, 16)?;
+            let mem_size = read_u32(header, 20)?;
+            let flags = read_u32(header, 24)?;
+
+            if file_size > mem_size || vaddr.checked_add(mem_size).is_none() {
+                return Err(LoadError::MalformedSegment(vaddr));
+            }
+            segments.push(Segment {
+                vaddr,
+                mem_size,
+                data: slice(data, offset, file_size as usize)?,
+                flags,
+            });
+        }
+
+        let mut gp = None;
+        let mut sp = None;
+        for i in 0..sh_num {
+            let header = slice(data, sh_off + i * sh_ent_size, SECTION_HEADER_SIZE)?;
+            if read_u32(header, 4)? != SHT_SYMTAB {
+                continue;
+            }
+            let symbols = slice(
+                data,
+                read_u32(header, 16)? as usize,
+                read_u32(header, 20)? as usize,
+            )?;
+            let str_header = slice(
+                data,
+                sh_off + read_u32(header, 24)? as usize * sh_ent_size,
+                SECTION_HEADER_SIZE,
+            )?;
+            let strings = slice(
+                data,
+                read_u32(str_header, 16)? as usize,
+                read_u32(str_header, 20)? as usize,
+            )?;
+
+            for symbol in symbols.chunks_exact(SYMBOL_SIZE) {
+                let name = c_str(strings, read_u32(symbol, 0)? as usize)?;
+                match name {
+                    b"_gp" => gp = Some(read_u32(symbol, 4)?),
+                    b"_sp" => sp = Some(read_u32(symbol, 4)?),
+                    _ => {}
+                }
+            }
+        }
+
+        Ok(Self {
+            entry,
+            gp,
+            sp: sp.unwrap_or(DEFAULT_STACK_POINTER),
+            segments,
+        })
+    }
+}
+
+fn slice(data: &[u8], offset: usize, len: usize) -> Result<&[u8], LoadError> {
+    data.get(offset..offset.checked_add(len).ok_or(LoadError::Truncated)?)
+        .ok_or(LoadError::Truncated)
+}
+
+fn c_str(data: &[u8], offset: usize) -> Result<&[u8], LoadError> {
+    let str = data.get(offset..).ok_or(LoadError::Truncated)?;
+    let len = str
+        .iter()
+        .position(|b| *b == 0)
+        .ok_or(LoadError::Truncated)?;
+    Ok(&str[..len])
+}
+
+fn read_u8(data: &[u8], offset: usize) -> Result<u8, LoadError> {
+    Ok(slice(data, offset, 1)?[0])
+}
+
+fn read_u16(data: &[u8], offset: usize) -> Result<u16, LoadError> {
+    Ok(u16::from_le_bytes(
+        slice(data, offset, 2)?.try_into().unwrap(),
+    ))
+}
+
+fn read_u32(data: &[u8], offset: usize) -> Result<u32, LoadError> {
+    Ok(u32::from_le_bytes(
+        slice(data, offset, 4)?.try_into().unwrap(),
+    ))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const CODE: [u8; 8] = [1, 2, 3, 4, 5, 6, 7, 8];
+
+    /// An executable with one loadable segment holding [`CODE`] and a symbol table defining
+    /// `_gp` and `_sp`
+    fn elf() -> Vec<u8> {
+        let mut data = vec![0; 52];
+        data[..4].copy_from_slice(&ELF_MAGIC);
+        data[4] = ELFCLASS32;
+        data[5] = ELFDATA2LSB;
+        let put = |data: &mut Vec<u8>, offset: usize, bytes: &[u8]| {
+            data[offset..offset + bytes.len()].copy_from_slice(bytes)
+        };
+        put(&mut data, 16, &ET_EXEC.to_le_bytes());
+        put(&mut data, 18, &EM_MIPS.to_le_bytes());
+        put(&mut data, 24, &0x400000u32.to_le_bytes());
+        put(&mut data, 28, &52u32.to_le_bytes());
+        put(&mut data, 42, &(PROGRAM_HEADER_SIZE as u16).to_le_bytes());
+        put(&mut data, 44, &1u16.to_le_bytes());
+        put(&mut data, 46, &(SECTION_HEADER_SIZE as u16).to_le_bytes());
+
+        let words = |words: &[u32]| {
+            words
+                .iter()
+                .flat_map(|w| w.to_le_bytes())
+                .collect::<Vec<_>>()
+        };
+        let code = 52 + PROGRAM_HEADER_SIZE as u32;
+        data.extend(words(&[PT_LOAD, code, 0x400000, 0, 8, 16, SEGMENT_READ, 0]));
+        data.extend(CODE);
+
+        let strings = data.len() as u32;
+        data.extend(b"\0_gp\0_sp\0");
+        let symbols = data.len() as u32;
+        data.extend(words(&[1, 0x1234, 0, 0, 5, 0x7000_0000, 0, 0]));
+
+        let sections = data.len() as u32;
+        put(&mut data, 32, &sections.to_le_bytes());
+        put(&mut data, 48, &2u16.to_le_bytes());
+        data.extend(words(&[0, SHT_SYMTAB, 0, 0, symbols, 32, 1, 0, 0, 16]));
+        data.extend(words(&[0, 3, 0, 0, strings, 9, 0, 0, 0, 0]));
+        data
+    }
+
+    #[test]
+    fn loads_segments_and_symbols() {
+        let data = elf();
+        let program = Program::load(&data).unwrap();
+        assert_eq!(program.entry, 0x400000);
+        assert_eq!(program.gp, Some(0x1234));
+        assert_eq!(program.sp, 0x7000_0000);
+        assert_eq!(program.segments.len(), 1);
+        let segment = &program.segments[0];
+        assert_eq!((segment.vaddr, segment.mem_size), (0x400000, 16));
+        assert_eq!(segment.data, CODE);
+    }
+
+    #[test]
+    fn every_truncated_file_is_an_error() {
+        let data = elf();
+        for len in 4..data.len() {
+            assert!(
+                matches!(Program::from_elf(&data[..len]), Err(LoadError::Truncated)),
+                "length {len}"
+            );
+        }
+    }
+
+    #[test]
+    fn rejects_other_files() {
+        let mut data = elf();
+        data[4] = 2;
+        assert!(matches!(
+            Program::from_elf(&data),
+            Err(LoadError::NotElf32LittleEndian)
+        ));
+        let mut data = elf();
+        data[18] = 3;
+        assert!(matches!(
+            Program::from_elf(&data),
+            Err(LoadError::NotMipsExecutable)
+        ));
+        assert!(matches!(Program::from_elf(&CODE), Err(LoadError::NotElf)));
+        assert_eq!(Program::load(&CODE).unwrap().segments[0].data, CODE);
+    }
+
+    #[test]
+    fn rejects_out_of_bounds_segments() {
+        let header = 52;
+        let set = |field: usize, val: u32| {
+            let mut data = elf();
+            data[header + field..header + field + 4].copy_from_slice(&val.to_le_bytes());
+            Program::from_elf(&data).map(|_| ())
+        };
+        // file data past the end of the file
+        assert!(matches!(set(4, 0xFFFF_FFF0), Err(LoadError::Truncated)));
+        assert!(matches!(
+            set(16, 0x1000),
+            Err(LoadError::MalformedSegment(_))
+        ));
+        assert!(matches!(
+            set(20, 0xFFFF_F000),
+            Err(LoadError::MalformedSegment(0x400000))
+        ));
+        // the symbol table points its names past the strings
+        let mut data = elf();
+        let strings = data.len() - 40 - 40 - 32 - 9;
+        data[strings + 4] = b'x';
+        data[strings + 8] = b'x';
+        assert!(matches!(
+            Program::from_elf(&data),
+            Err(LoadError::Truncated)
+        ));
+    }
+}
@@ -1,6 +1,7 @@
+use std::collections::{HashMap, VecDeque};
 use std::{collections::BinaryHeap, time::Duration};
 
-use crate::util::{TaskId, ThreadId};
+use crate::util::{Page, ProcessId, TaskId, ThreadId};
 use crate::SystemTime;
 
 /// The Scheduler to schedule what task will run and for how long
@@ -10,6 +11,7 @@ use crate::SystemTime;
 pub struct Scheduler {
     task_list: BinaryHeap<SchedulerTask>,
     tasks_to_remove: Vec<TaskId>,
+    blocked: HashMap<WaitKey, VecDeque<SchedulerTask>>,
 
     average_instructions: RollingAverage,
     average_vm_duration: RollingAverage,
@@ -33,6 +35,35 @@ impl SchedulerTask {
     }
 }
 
+/// Identifies a physical word in memory that tasks can wait on.
+///
+/// Futexes are keyed by the physical page and not by the virtual address so that two tasks that
+/// map the same page at different addresses still wait on the same queue
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub struct FutexKey {
+    page: usize,
+    offset: u16,
+}
+
+impl FutexKey {
+    pub fn new(page: &Page, offset: u16) -> Self {
+        Self {
+            page: page as *const Page as usize,
+            offset,
+        }
+    }
+}
+
+/// Something a blocked task is waiting on before it can be scheduled again
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub enum WaitKey {
+    Futex(FutexKey),
+    /// Waiting for the task to exit
+    Join(TaskId),
+    /// Waiting for the process to end
+    Process(ProcessId),
+}
+
 impl SchedulerTask {
     pub fn time_available_to_run(&self) -> SystemTime {
         if let Some(sleep) = self.sleep_for {
@@ -92,9 +123,74 @@ impl Scheduler {
     }
 
     pub fn remove_task(&mut self, tid: TaskId) {
-        // self.task_list.retain(|task|{});
-        // todo!()
-        self.tasks_to_remove.push(tid);
+        // blocked tasks will never be popped from the run queue so they are removed right away
+        let mut was_blocked = false;
+        self.blocked.retain(|_, queue| {
+            if let Some(index) = queue.iter().position(|task| task.tid().0 == tid) {
+                queue.remove(index);
+                was_blocked = true;
+            }
+            !queue.is_empty()
+        });
+        if !was_blocked {
+            self.tasks_to_remove.push(tid);
+        }
+    }
+
+    /// Parks a task until it is woken with [`Scheduler::wake`]. Blocked tasks are not part of the
+    /// run queue and will not be scheduled
+    pub fn block_task(&mut self, task: SchedulerTask, key: WaitKey) {
+        self.blocked.entry(key).or_default().push_back(task);
+    }
+
+    /// Moves up to `count` tasks waiting on `key` back onto the run queue in the order they
+    /// blocked. Returns the number of tasks actually woken
+    pub fn wake(&mut self, key: WaitKey, count: u32) -> u32 {
+        let Some(queue) = self.blocked.get_mut(&key) else {
+            return 0;
+        };
+        let mut woken = 0;
+        while woken < count {
+            let Some(task) = queue.pop_front() else {
+                break;
+            };
+            self.task_list.push(task);
+            woken += 1;
+        }
+        if queue.is_empty() {
+            self.blocked.remove(&key);
+        }
+        woken
+    }
+
+    /// Moves the tasks of `pid` waiting on a futex in `from` to the same word in `to`, for when the
+    /// process got its own copy of a page. Tasks of other processes keep waiting on `from`
+    pub fn move_futex_waiters(&mut self, from: &Page, to: &Page, pid: ProcessId) {
+        let from = from as *const Page as usize;
+        let keys: Vec<FutexKey> = self
+            .blocked
+            .keys()
+            .filter_map(|key| match key {
+                WaitKey::Futex(futex) if futex.page == from => Some(*futex),
+                _ => None,
+            })
+            .collect();
+        for key in keys {
+            let queue = self.blocked.remove(&WaitKey::Futex(key)).unwrap();
+            let (moved, stayed): (VecDeque<_>, VecDeque<_>) =
+                queue.into_iter().partition(|task| task.tid().1 == pid);
+            if !stayed.is_empty() {
+                self.blocked.insert(WaitKey::Futex(key), stayed);
+            }
+            if !moved.is_empty() {
+                let key = WaitKey::Futex(FutexKey::new(to, key.offset));
+                self.blocked.entry(key).or_default().extend(moved);
+            }
+        }
+    }
+
+    pub fn blocked_tasks(&self) -> usize {
+        self.blocked.values().map(VecDeque::len).sum()
     }
 
     pub fn schedule_next_task(&mut self) -> Option<(SchedulerTask, u32)> {
@@ -160,3 +256,44 @@ impl RollingAverage {
         self.average
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn thread(id: u32) -> ThreadId {
+        (TaskId::from_raw(id), ProcessId::from_raw(1))
+    }
+
+    #[test]
+    fn wake_counts_only_tasks_waiting_on_the_key() {
+        let page = Page::new();
+        let (key, other) = (
+            WaitKey::Futex(FutexKey::new(&page, 0)),
+            WaitKey::Futex(FutexKey::new(&page, 4)),
+        );
+        let mut scheduler = Scheduler::default();
+        for id in 1..=3 {
+            scheduler.block_task(SchedulerTask::new(thread(id)), key);
+        }
+        scheduler.block_task(SchedulerTask::new(thread(4)), other);
+
+        assert_eq!(scheduler.wake(key, 2), 2);
+        assert_eq!(scheduler.blocked_tasks(), 2);
+        assert_eq!(scheduler.wake(key, u32::MAX), 1);
+        assert_eq!(scheduler.wake(key, u32::MAX), 0);
+        assert_eq!(scheduler.wake(other, 0), 0);
+        assert_eq!(scheduler.blocked_tasks(), 1);
+    }
+
+    #[test]
+    fn removed_tasks_leave_their_wait_queue() {
+        let key = WaitKey::Join(TaskId::from_raw(9));
+        let mut scheduler = Scheduler::default();
+        scheduler.block_task(SchedulerTask::new(thread(1)), key);
+        scheduler.block_task(SchedulerTask::new(thread(2)), key);
+        scheduler.remove_task(TaskId::from_raw(1));
+        assert_eq!(scheduler.wake(key, u32::MAX), 1);
+        assert_eq!(scheduler.schedule_next_task().unwrap().0.tid(), thread(2));
+    }
+}
@@ -0,0 +1,48 @@
+use rclite::Arc;
+
+use crate::taskpool::{FillPolicy, TaskPoolSharedMemory};
+
+use super::System;
+
+/// Settings a [`System`] is created with, [`System::default`] uses the defaults of every one
+#[derive(Debug, Default, Clone)]
+pub struct SystemBuilder {
+    fill: FillPolicy,
+    track_uninitialized_reads: bool,
+}
+
+impl SystemBuilder {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// What memory holds before a task writes it, [`FillPolicy::DebugPattern`] by default
+    pub fn fill_policy(mut self, fill: FillPolicy) -> Self {
+        self.fill = fill;
+        self
+    }
+
+    /// Whether to track every written word and report loads of words that were never written
+    /// with the pc of the load. Memory the system hands out zeroed, like mapped memory and
+    /// .bss, counts as written. This slows down every load and store
+    pub fn track_uninitialized_reads(mut self, track: bool) -> Self {
+        self.track_uninitialized_reads = track;
+        self
+    }
+
+    pub fn build(self) -> System {
+        System {
+            sys_mem: Arc::new(TaskPoolSharedMemory::new(
+                self.fill,
+                self.track_uninitialized_reads,
+            )),
+            ..Default::default()
+        }
+    }
+}
+
+impl System {
+    pub fn builder() -> SystemBuilder {
+        SystemBuilder::new()
+    }
+}
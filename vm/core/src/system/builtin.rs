@@ -0,0 +1,594 @@
+//! The system calls every [`System`] starts out with
+
+use std::time::{Duration, SystemTime};
+
+use crate::{
+    scheduler::{FutexKey, SchedulerTask, WaitKey},
+    task::{
+        MemoryAccess, PageVAddressStart, Protection, Task, TaskError, TaskMemory,
+        DEFAULT_STACK_SIZE,
+    },
+    util::{page_address, page_number, page_offset, pages_for, ProcessId, TaskId, PAGE_COUNT},
+};
+
+use super::{InterfaceCallResult, Limit, System, SystemCallTable};
+
+pub const HALT: u32 = 0;
+pub const PRINT_DEC_NUMBER: u32 = 1;
+pub const PRINT_C_STRING: u32 = 4;
+pub const PRINT_CHAR: u32 = 5;
+pub const EXIT_PROCESS: u32 = 10;
+pub const CLONE_PROCESS: u32 = 11;
+pub const PROCESS_WAIT: u32 = 12;
+pub const CURRENT_TIME_NANOS: u32 = 60;
+pub const START_NEW_THREAD: u32 = 100;
+pub const SLEEP_NANOS: u32 = 101;
+pub const WAIT_CONTINUE: u32 = 102;
+pub const THREAD_JOIN: u32 = 103;
+pub const THREAD_DETACH: u32 = 104;
+pub const FUTEX_WAKE: u32 = 200;
+pub const FUTEX_WAIT: u32 = 201;
+pub const MAP_MEMORY: u32 = 300;
+pub const UNMAP_MEMORY: u32 = 301;
+pub const OPEN_SHARED_MEMORY: u32 = 310;
+pub const MAP_SHARED_MEMORY: u32 = 311;
+pub const CLOSE_SHARED_MEMORY: u32 = 312;
+
+pub(super) fn register(table: &mut SystemCallTable) {
+    table.register(HALT, halt);
+    table.register(PRINT_DEC_NUMBER, print_dec_number);
+    table.register(PRINT_C_STRING, print_c_string);
+    table.register(PRINT_CHAR, print_char);
+    table.register(EXIT_PROCESS, exit_process);
+    table.register(CLONE_PROCESS, clone_process);
+    table.register(PROCESS_WAIT, process_wait);
+    table.register(CURRENT_TIME_NANOS, current_time_nanos);
+    table.register(START_NEW_THREAD, start_new_thread);
+    table.register(SLEEP_NANOS, sleep_nanos);
+    table.register(WAIT_CONTINUE, wait_continue);
+    table.register(THREAD_JOIN, thread_join);
+    table.register(THREAD_DETACH, thread_detach);
+    table.register(FUTEX_WAKE, futex_wake);
+    table.register(FUTEX_WAIT, futex_wait);
+    table.register(MAP_MEMORY, map_memory);
+    table.register(UNMAP_MEMORY, unmap_memory);
+    table.register(OPEN_SHARED_MEMORY, open_shared_memory);
+    table.register(MAP_SHARED_MEMORY, map_shared_memory);
+    table.register(CLOSE_SHARED_MEMORY, close_shared_memory);
+}
+
+fn halt(
+    _sys: &mut System,
+    task: &mut Task,
+    _scheduler_task: &mut SchedulerTask,
+    _mem: &mut TaskMemory<'_>,
+) -> InterfaceCallResult {
+    InterfaceCallResult::Exit(task.vm_state.reg[4])
+}
+
+fn print_dec_number(
+    _sys: &mut System,
+    task: &mut Task,
+    _scheduler_task: &mut SchedulerTask,
+    _mem: &mut TaskMemory<'_>,
+) -> InterfaceCallResult {
+    tracing::info!("Task: {} -> {}", task.tid(), task.vm_state.reg[4] as i32);
+    InterfaceCallResult::Continue
+}
+
+fn print_c_string(
+    _sys: &mut System,
+    task: &mut Task,
+    _scheduler_task: &mut SchedulerTask,
+    mem: &mut TaskMemory<'_>,
+) -> InterfaceCallResult {
+    let str = match mem.read_c_string(task.vm_state.reg[4], task.vm_state.pc) {
+        Ok(str) => str,
+        Err(err) => return err.into(),
+    };
+
+    let str = String::from_utf8(str);
+    if let Ok(str) = str {
+        tracing::info!("Task: {} -> {}", task.tid(), str);
+    } else {
+        return InterfaceCallResult::MalformedCallArgs;
+    }
+    InterfaceCallResult::Continue
+}
+
+fn print_char(
+    sys: &mut System,
+    task: &mut Task,
+    _scheduler_task: &mut SchedulerTask,
+    _mem: &mut TaskMemory<'_>,
+) -> InterfaceCallResult {
+    let char = task.vm_state.reg[4] as u8 as char;
+    if char != '\n' {
+        if let std::collections::hash_map::Entry::Vacant(e) =
+            sys.core.partial_process_output.entry(task.tid())
+        {
+            e.insert(char.into());
+        } else {
+            sys.core
+                .partial_process_output
+                .get_mut(&task.tid())
+                .unwrap()
+                .push(char);
+        }
+    } else if let Some(msg) = sys.core.partial_process_output.remove(&task.tid()) {
+        tracing::info!("Task: {} -> {}", task.tid(), msg);
+    } else {
+        tracing::info!("Task: {} -> ", task.tid());
+    }
+    InterfaceCallResult::Continue
+}
+
+fn exit_process(
+    _sys: &mut System,
+    task: &mut Task,
+    _scheduler_task: &mut SchedulerTask,
+    _mem: &mut TaskMemory<'_>,
+) -> InterfaceCallResult {
+    InterfaceCallResult::ExitProcess(task.vm_state.reg[4])
+}
+
+/// Starts a new process running the calling thread with a copy on write copy of its memory
+///
+/// Both continue after the system call, v0 holds the id of the new process in the caller and 0 in
+/// the new process
+fn clone_process(
+    sys: &mut System,
+    task: &mut Task,
+    _scheduler_task: &mut SchedulerTask,
+    mem: &mut TaskMemory<'_>,
+) -> InterfaceCallResult {
+    let mut child = Task::new_mainthread(sys.next_task_id());
+    child.memory_mapping = task.memory_mapping.fork();
+    child.vm_state = task.vm_state.clone();
+    child.vm_state.reg[2] = 0;
+    child.name = task.name.clone();
+    child.delay_slots = task.delay_slots;
+
+    // pages this task could write before are copy on write now
+    task.memory_mapping
+        .for_each_mapping(|_, v_page, protection| mem.protection[v_page as usize] = protection);
+
+    let mut pages = Vec::new();
+    child
+        .memory_mapping
+        .for_each_mapping(|page, _, _| pages.push(page.clone()));
+    sys.sys_mem
+        .share_pages(pages.iter().map(|page| &**page), child.thread_id().1);
+
+    tracing::info!(
+        "Process: {} cloned into: {}",
+        task.thread_id().1,
+        child.tid()
+    );
+    task.vm_state.reg[2] = child.tid().into_raw();
+    let (parent, child_pid) = (task.thread_id().1, child.thread_id().1);
+    sys.add_task(child);
+
+    // the clone can't get around the limits of its parent and keeps its handles
+    sys.set_process_limits(child_pid, sys.process_limits(parent));
+    for id in sys.core.processes[&parent].handles.clone() {
+        sys.add_handle(child_pid, id);
+    }
+    InterfaceCallResult::Continue
+}
+
+fn process_wait(
+    sys: &mut System,
+    task: &mut Task,
+    _scheduler_task: &mut SchedulerTask,
+    _mem: &mut TaskMemory<'_>,
+) -> InterfaceCallResult {
+    let target = match ProcessId::new(task.vm_state.reg[4]) {
+        Some(target) if target != task.thread_id().1 => target,
+        _ => {
+            task.vm_state.reg[3] = 2;
+            return InterfaceCallResult::Continue;
+        }
+    };
+
+    match sys.core.exited_processes.remove(&target) {
+        Some(Some(code)) => {
+            task.vm_state.reg[2] = code;
+            task.vm_state.reg[3] = 0;
+        }
+        Some(None) => {
+            task.vm_state.reg[2] = 0;
+            task.vm_state.reg[3] = 1;
+        }
+        None if sys.core.processes.contains_key(&target) => {
+            return InterfaceCallResult::BlockRepeated(WaitKey::Process(target));
+        }
+        None => {
+            task.vm_state.reg[3] = 2;
+        }
+    }
+    InterfaceCallResult::Continue
+}
+
+fn current_time_nanos(
+    _sys: &mut System,
+    task: &mut Task,
+    _scheduler_task: &mut SchedulerTask,
+    _mem: &mut TaskMemory<'_>,
+) -> InterfaceCallResult {
+    let time = crate::systime_now()
+        .duration_since(SystemTime::UNIX_EPOCH)
+        .unwrap();
+    let dur = time.as_nanos() as u64;
+    task.vm_state.reg[2] = dur as u32;
+    task.vm_state.reg[3] = (dur >> 32) as u32;
+    InterfaceCallResult::Continue
+}
+
+fn start_new_thread(
+    sys: &mut System,
+    task: &mut Task,
+    _scheduler_task: &mut SchedulerTask,
+    _mem: &mut TaskMemory<'_>,
+) -> InterfaceCallResult {
+    let stack_size = task.vm_state.reg[6];
+    let stack_size = if stack_size == 0 {
+        DEFAULT_STACK_SIZE
+    } else {
+        stack_size
+    };
+    let stack_pages = pages_for(stack_size as u64) as u32;
+    let pid = task.thread_id().1;
+    if !sys.within_limit(pid, Limit::Threads, 1)
+        || !sys.within_limit(pid, Limit::Pages, stack_pages as u64)
+    {
+        task.vm_state.reg[2] = 0;
+        return InterfaceCallResult::Continue;
+    }
+    let stack = task
+        .memory_mapping
+        .address_space
+        .lock()
+        .unwrap()
+        .allocate_stack(stack_pages);
+    let Some(stack) = stack else {
+        // no room left for another stack
+        task.vm_state.reg[2] = 0;
+        return InterfaceCallResult::Continue;
+    };
+
+    let mut new_task = Task::new_subthread(task.thread_id().1, sys.next_task_id());
+    new_task.memory_mapping = task.memory_mapping.new_thread();
+    new_task.delay_slots = task.delay_slots;
+    // the exception handler belongs to the process so new threads use it too
+    new_task.vm_state.cop0.ebase = task.vm_state.cop0.ebase;
+    new_task
+        .memory_mapping
+        .map_stack(stack, || sys.sys_mem.new_page(task.thread_id().1));
+
+    new_task.vm_state.pc = task.vm_state.reg[4];
+    new_task.vm_state.reg[4] = task.vm_state.reg[5];
+    new_task.vm_state.reg[29] = stack.stack_pointer(); //start of stack
+    new_task.vm_state.reg[30] = stack.stack_pointer();
+    new_task.vm_state.reg[31] = 0xFFFFFFFF;
+
+    tracing::info!(
+        "Created new task: {}\nDUMP{{:?}}",
+        new_task.tid(),
+        //new_task
+    );
+    task.vm_state.reg[2] = new_task.tid().into_raw();
+    sys.add_task(new_task);
+    InterfaceCallResult::Continue
+}
+
+fn sleep_nanos(
+    _sys: &mut System,
+    task: &mut Task,
+    scheduler_task: &mut SchedulerTask,
+    _mem: &mut TaskMemory<'_>,
+) -> InterfaceCallResult {
+    let val = task.vm_state.reg[4] as u64 | ((task.vm_state.reg[5] as u64) << 32);
+    let dur = Duration::from_nanos(val);
+    scheduler_task.sleep_for = Some(dur);
+    //23479387.80014355
+    //248832255.48680574
+    //233960039
+    InterfaceCallResult::Wait
+}
+
+fn wait_continue(
+    _sys: &mut System,
+    _task: &mut Task,
+    _scheduler_task: &mut SchedulerTask,
+    _mem: &mut TaskMemory<'_>,
+) -> InterfaceCallResult {
+    // stop doing tings and stuff and
+    InterfaceCallResult::Wait
+}
+
+fn thread_join(
+    sys: &mut System,
+    task: &mut Task,
+    _scheduler_task: &mut SchedulerTask,
+    _mem: &mut TaskMemory<'_>,
+) -> InterfaceCallResult {
+    let target = match TaskId::new(task.vm_state.reg[4]) {
+        Some(target) if target != task.tid() => target,
+        _ => {
+            task.vm_state.reg[3] = 2;
+            return InterfaceCallResult::Continue;
+        }
+    };
+
+    let pid = task.thread_id().1;
+    let exited = sys
+        .core
+        .processes
+        .get_mut(&pid)
+        .and_then(|process| process.exited_threads.remove(&target));
+    match exited {
+        Some(code) => {
+            task.vm_state.reg[2] = code;
+            task.vm_state.reg[3] = 0;
+        }
+        None if is_joinable(sys, target, pid) => {
+            return InterfaceCallResult::BlockRepeated(WaitKey::Join(target));
+        }
+        None => {
+            task.vm_state.reg[3] = 2;
+        }
+    }
+    InterfaceCallResult::Continue
+}
+
+/// Whether `target` is a running thread of `pid` that wasn't detached, it must not be the
+/// calling thread as that one is locked
+fn is_joinable(sys: &System, target: TaskId, pid: ProcessId) -> bool {
+    let detached = sys
+        .core
+        .processes
+        .get(&pid)
+        .is_some_and(|process| process.detached_threads.contains(&target));
+    !detached
+        && sys
+            .tasks
+            .task_pool
+            .get(&target)
+            .is_some_and(|target| target.lock().unwrap().thread_id().1 == pid)
+}
+
+/// Lets go of a thread of the process so its exit code isn't kept for a join
+///
+/// a0 is the id of the thread, which may be the calling thread. v0 holds 1 if the thread was
+/// detached and 0 if the process has no such thread to join
+fn thread_detach(
+    sys: &mut System,
+    task: &mut Task,
+    _scheduler_task: &mut SchedulerTask,
+    _mem: &mut TaskMemory<'_>,
+) -> InterfaceCallResult {
+    let Some(target) = TaskId::new(task.vm_state.reg[4]) else {
+        task.vm_state.reg[2] = 0;
+        return InterfaceCallResult::Continue;
+    };
+    let pid = task.thread_id().1;
+    let running = target == task.tid() || is_joinable(sys, target, pid);
+    let process = sys.core.processes.get_mut(&pid).unwrap();
+    let detached = if process.exited_threads.remove(&target).is_some() {
+        true
+    } else {
+        running && process.detached_threads.insert(target)
+    };
+    task.vm_state.reg[2] = detached as u32;
+    InterfaceCallResult::Continue
+}
+
+fn futex_wake(
+    sys: &mut System,
+    task: &mut Task,
+    _scheduler_task: &mut SchedulerTask,
+    mem: &mut TaskMemory<'_>,
+) -> InterfaceCallResult {
+    let futex_addr = task.vm_state.reg[4];
+    let tasks_to_wake = task.vm_state.reg[5];
+
+    let key = match futex_key(sys, futex_addr, task, mem) {
+        Ok(key) => key,
+        Err(err) => return err.into(),
+    };
+    task.vm_state.reg[2] = sys.core.scheduler.wake(WaitKey::Futex(key), tasks_to_wake);
+    InterfaceCallResult::Continue
+}
+
+fn futex_wait(
+    sys: &mut System,
+    task: &mut Task,
+    _scheduler_task: &mut SchedulerTask,
+    mem: &mut TaskMemory<'_>,
+) -> InterfaceCallResult {
+    let futex_addr = task.vm_state.reg[4];
+    let condition = task.vm_state.reg[5];
+
+    let key = match futex_key(sys, futex_addr, task, mem) {
+        Ok(key) => key,
+        Err(err) => return err.into(),
+    };
+    let val = match mem.read_u32(futex_addr, task.vm_state.pc) {
+        Ok(val) => val,
+        Err(err) => return err.into(),
+    };
+    if val != condition {
+        task.vm_state.reg[2] = 0;
+        return InterfaceCallResult::Continue;
+    }
+    task.vm_state.reg[2] = 1;
+    InterfaceCallResult::Block(WaitKey::Futex(key))
+}
+
+fn futex_key(
+    sys: &mut System,
+    futex_addr: u32,
+    task: &mut Task,
+    mem: &mut TaskMemory<'_>,
+) -> Result<FutexKey, TaskError> {
+    if futex_addr & 0b11 != 0 {
+        return Err(TaskError::MemoryAllignmentError(
+            4,
+            MemoryAccess::Read,
+            futex_addr,
+            task.vm_state.pc,
+        ));
+    }
+    let page = match mem.page(futex_addr) {
+        Some(page) => page,
+        // a futex in memory that was never touched still has to work
+        None => task.fault_in(sys, mem, futex_addr, MemoryAccess::Read)?,
+    };
+    Ok(FutexKey::new(page, page_offset(futex_addr)))
+}
+
+/// Reserves zeroed memory in the address space of the process, pages only take up memory once
+/// they are first touched
+///
+/// a0 is the address the mapping should preferably start at (rounded up to a page, 0 for
+/// anywhere) and a1 its length in bytes. If the preferred range is taken the pages are placed
+/// anywhere else that is free. v0 holds the start of the mapping and v1 its length rounded up to
+/// whole pages, or both are 0 when the address space is full
+fn map_memory(
+    sys: &mut System,
+    task: &mut Task,
+    _scheduler_task: &mut SchedulerTask,
+    _mem: &mut TaskMemory<'_>,
+) -> InterfaceCallResult {
+    let hint = pages_for(task.vm_state.reg[4] as u64);
+    let pages = pages_for(task.vm_state.reg[5] as u64) as u32;
+
+    let address_space = task.memory_mapping.address_space.clone();
+    let mut address_space = address_space.lock().unwrap();
+    let start = if pages == 0 || !sys.within_limit(task.thread_id().1, Limit::Pages, pages as u64) {
+        None
+    } else if hint != 0 && address_space.is_free(hint as PageVAddressStart, pages) {
+        Some(hint as PageVAddressStart)
+    } else {
+        address_space.find_free(pages)
+    };
+    let Some(start) = start else {
+        task.vm_state.reg[2] = 0;
+        task.vm_state.reg[3] = 0;
+        return InterfaceCallResult::Continue;
+    };
+    address_space.reserve(start, pages, Protection::READ_WRITE);
+
+    task.vm_state.reg[2] = page_address(start);
+    task.vm_state.reg[3] = page_address(pages);
+    InterfaceCallResult::Continue
+}
+
+/// Unmaps every page in a range of the address space of the process
+///
+/// a0 is the start of the range, rounded down to a page, and a1 its length in bytes. Stacks are
+/// left alone. v0 holds the number of pages that were unmapped, counting reserved pages that
+/// were never touched
+fn unmap_memory(
+    sys: &mut System,
+    task: &mut Task,
+    _scheduler_task: &mut SchedulerTask,
+    mem: &mut TaskMemory<'_>,
+) -> InterfaceCallResult {
+    let start = page_number(task.vm_state.reg[4]);
+    let end = pages_for(page_address(start) as u64 + task.vm_state.reg[5] as u64)
+        .min(PAGE_COUNT as u64) as PageVAddressStart;
+
+    let address_space = task.memory_mapping.address_space.clone();
+    let mut address_space = address_space.lock().unwrap();
+    let mut unmapped = 0;
+    let mut released = Vec::new();
+    for v_page in start..end {
+        if address_space.in_stack_region(v_page) {
+            continue;
+        }
+        let reserved = address_space.reservation(v_page).is_some();
+        let mapped = address_space.unmap(v_page);
+        if mapped.is_some() || reserved {
+            unmapped += 1;
+        }
+        if let Some(page) = mapped {
+            mem.unmap_page(v_page);
+            released.push(page);
+        }
+    }
+    address_space.unreserve(start, end);
+    drop(address_space);
+
+    sys.sys_mem.reclaim_pages(released);
+    task.vm_state.reg[2] = unmapped;
+    InterfaceCallResult::Continue
+}
+
+/// Opens a named shared memory object, creating it if no process has done so yet
+///
+/// a0 points to the \0 terminated name and a1 is the length in bytes the object is created with,
+/// rounded up to whole pages. An object that already exists keeps its length. v0 holds the id of
+/// the object and v1 its length, or both are 0 if it doesn't exist and a1 is 0 or the process
+/// can't open another object
+fn open_shared_memory(
+    sys: &mut System,
+    task: &mut Task,
+    _scheduler_task: &mut SchedulerTask,
+    mem: &mut TaskMemory<'_>,
+) -> InterfaceCallResult {
+    let name = match mem.read_c_string(task.vm_state.reg[4], task.vm_state.pc) {
+        Ok(name) => name,
+        Err(err) => return err.into(),
+    };
+    let Ok(name) = String::from_utf8(name) else {
+        return InterfaceCallResult::MalformedCallArgs;
+    };
+    let pages = pages_for(task.vm_state.reg[5] as u64) as u32;
+
+    let (id, pages) = sys
+        .open_shared_memory(&name, pages, task.thread_id().1)
+        .unwrap_or((0, 0));
+    task.vm_state.reg[2] = id;
+    task.vm_state.reg[3] = page_address(pages);
+    InterfaceCallResult::Continue
+}
+
+/// Maps a shared memory object into the address space of the process
+///
+/// a0 is the id of the object and a1 the address the mapping should preferably start at (rounded
+/// up to a page, 0 for anywhere). v0 holds the start of the mapping and v1 its length, or both
+/// are 0 if the process hasn't opened the object or there was no room for it. The mapping is
+/// removed again with [`UNMAP_MEMORY`]
+fn map_shared_memory(
+    sys: &mut System,
+    task: &mut Task,
+    _scheduler_task: &mut SchedulerTask,
+    mem: &mut TaskMemory<'_>,
+) -> InterfaceCallResult {
+    let id = task.vm_state.reg[4];
+    let hint = pages_for(task.vm_state.reg[5] as u64);
+
+    let (start, pages) = u32::try_from(hint)
+        .ok()
+        .and_then(|hint| sys.map_shared_memory(task, mem, id, hint))
+        .unwrap_or((0, 0));
+    task.vm_state.reg[2] = page_address(start);
+    task.vm_state.reg[3] = page_address(pages);
+    InterfaceCallResult::Continue
+}
+
+/// Closes a shared memory object, processes that have it mapped keep their mapping
+///
+/// a0 is the id of the object. v0 holds 1 if the object was closed and 0 if the process hasn't
+/// opened it
+fn close_shared_memory(
+    sys: &mut System,
+    task: &mut Task,
+    _scheduler_task: &mut SchedulerTask,
+    _mem: &mut TaskMemory<'_>,
+) -> InterfaceCallResult {
+    task.vm_state.reg[2] = sys.close_shared_memory(task.vm_state.reg[4], task.thread_id().1) as u32;
+    InterfaceCallResult::Continue
+}
@@ -0,0 +1,128 @@
+//! Backing memory the first time a task touches it
+
+use rclite::Arc;
+
+use crate::{
+    task::{
+        MemoryAccess, PageVAddressStart, Protection, Task, TaskError, VmInstructionAddress, VmPtr,
+    },
+    util::{page_number, Page, ProcessId},
+};
+
+use super::{Limit, System};
+
+/// An access by a task to an address where nothing is mapped
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct PageFault {
+    pub address: VmPtr,
+    pub access: MemoryAccess,
+    pub pc: VmInstructionAddress,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum PageFaultResult {
+    /// A page was mapped at the faulting address, the access is tried again
+    Mapped,
+    /// The handler doesn't know the address, reserved memory is still zero filled and anything
+    /// else ends the task
+    Unhandled,
+}
+
+/// A host side hook that gets to back unmapped pages before the task faults. Any `FnMut` with
+/// the same signature as [`PageFaultHandler::page_fault`] is a handler
+pub trait PageFaultHandler {
+    fn page_fault(
+        &mut self,
+        sys: &mut System,
+        task: &mut Task,
+        fault: PageFault,
+    ) -> PageFaultResult;
+}
+
+impl<F> PageFaultHandler for F
+where
+    F: FnMut(&mut System, &mut Task, PageFault) -> PageFaultResult,
+{
+    fn page_fault(
+        &mut self,
+        sys: &mut System,
+        task: &mut Task,
+        fault: PageFault,
+    ) -> PageFaultResult {
+        self(sys, task, fault)
+    }
+}
+
+impl System {
+    /// Installs `handler` for page faults of every task, returning the handler it replaced
+    pub fn set_page_fault_handler(
+        &mut self,
+        handler: impl PageFaultHandler + 'static,
+    ) -> Option<Box<dyn PageFaultHandler>> {
+        self.core.page_fault_handler.replace(Box::new(handler))
+    }
+
+    pub fn clear_page_fault_handler(&mut self) -> Option<Box<dyn PageFaultHandler>> {
+        self.core.page_fault_handler.take()
+    }
+
+    /// Maps a zeroed page at `v_page` into the address space of the process of `task` and returns
+    /// it so the caller can fill it
+    pub fn map_zeroed_page(
+        &self,
+        task: &Task,
+        v_page: PageVAddressStart,
+        protection: Protection,
+    ) -> Arc<Page> {
+        let page = self.sys_mem.new_zeroed_page(task.thread_id().1);
+        task.memory_mapping
+            .address_space
+            .lock()
+            .unwrap()
+            .map(page.clone(), v_page, protection);
+        page
+    }
+
+    /// Lets the futex waiters of `pid` follow it from the copy on write page `shared` to `copy`,
+    /// the copy it got of the page
+    pub(crate) fn page_copied(&mut self, pid: ProcessId, shared: &Page, copy: &Page) {
+        self.core.scheduler.move_futex_waiters(shared, copy, pid);
+    }
+
+    /// Gives the page fault handler a chance to map the faulting page, falling back to zeroed
+    /// pages for reserved memory. Returns whether a page was mapped, or an error if the process
+    /// isn't allowed another page
+    pub(crate) fn handle_page_fault(
+        &mut self,
+        task: &mut Task,
+        fault: PageFault,
+    ) -> Result<bool, TaskError> {
+        // the handler is taken out while it runs so it can be given the whole system
+        if let Some(mut handler) = self.core.page_fault_handler.take() {
+            let res = handler.page_fault(self, task, fault);
+            // unless the handler installed a new one put it back
+            self.core.page_fault_handler.get_or_insert(handler);
+            if res == PageFaultResult::Mapped {
+                return Ok(true);
+            }
+        }
+
+        let v_page = page_number(fault.address);
+        let reserved = task
+            .memory_mapping
+            .address_space
+            .lock()
+            .unwrap()
+            .reservation(v_page);
+        match reserved {
+            Some(_) if !self.within_limit(task.thread_id().1, Limit::Pages, 1) => {
+                Err(TaskError::LimitExceeded(Limit::Pages, fault.pc))
+            }
+            Some(protection) => {
+                self.map_zeroed_page(task, v_page, protection);
+                Ok(true)
+            }
+            None => Ok(false),
+        }
+    }
+}
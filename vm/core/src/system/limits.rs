@@ -0,0 +1,186 @@
+//! Limits on how much of the system a single process may use
+
+use crate::{
+    loader::Program,
+    task::{TaskError, TaskRunResult},
+    util::{ProcessId, TaskId},
+};
+
+use super::System;
+
+/// A resource a process can run out of, see [`ProcessLimits`]
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Limit {
+    Pages,
+    Threads,
+    Handles,
+    Instructions,
+}
+
+/// Upper bounds for a single process, `None` means unlimited. A cloned process starts out with
+/// the limits of the process it was cloned from
+///
+/// Running into a limit in a system call makes the call fail, running into it anywhere else
+/// kills the process with [`TaskError::LimitExceeded`]
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub struct ProcessLimits {
+    /// Pages the process may own at once, a page shared by several processes counts for each
+    pub max_pages: Option<usize>,
+    /// Threads that may exist at once, including the main thread
+    pub max_threads: Option<usize>,
+    /// Shared memory objects the process may have open at once
+    pub max_handles: Option<usize>,
+    /// Instructions the process may run in total
+    pub max_instructions: Option<u64>,
+}
+
+impl ProcessLimits {
+    pub const UNLIMITED: Self = Self {
+        max_pages: None,
+        max_threads: None,
+        max_handles: None,
+        max_instructions: None,
+    };
+
+    pub fn max(&self, limit: Limit) -> Option<u64> {
+        match limit {
+            Limit::Pages => self.max_pages.map(|max| max as u64),
+            Limit::Threads => self.max_threads.map(|max| max as u64),
+            Limit::Handles => self.max_handles.map(|max| max as u64),
+            Limit::Instructions => self.max_instructions,
+        }
+    }
+}
+
+impl System {
+    /// Like [`System::add_program`] but the new process can't use more than `limits` allows
+    pub fn add_program_with_limits(
+        &mut self,
+        program: &Program<'_>,
+        limits: ProcessLimits,
+    ) -> TaskId {
+        let tid = self.add_program(program);
+        self.set_process_limits(tid.to_pid(), limits);
+        tid
+    }
+
+    pub fn process_limits(&self, pid: ProcessId) -> ProcessLimits {
+        self.core
+            .processes
+            .get(&pid)
+            .map_or(ProcessLimits::UNLIMITED, |process| process.limits)
+    }
+
+    pub(super) fn set_process_limits(&mut self, pid: ProcessId, limits: ProcessLimits) {
+        if let Some(process) = self.core.processes.get_mut(&pid) {
+            process.limits = limits;
+        }
+    }
+
+    /// How much of `limit` the process `pid` currently uses
+    pub fn process_usage(&self, pid: ProcessId, limit: Limit) -> u64 {
+        match limit {
+            Limit::Pages => self.sys_mem.v_mem.read().unwrap().process_pages(pid) as u64,
+            Limit::Threads => self
+                .core
+                .processes
+                .get(&pid)
+                .map_or(0, |process| process.threads as u64),
+            Limit::Handles => self
+                .core
+                .processes
+                .get(&pid)
+                .map_or(0, |process| process.handles.len() as u64),
+            Limit::Instructions => self
+                .core
+                .processes
+                .get(&pid)
+                .map_or(0, |process| process.instructions),
+        }
+    }
+
+    /// Whether the process `pid` may use `extra` more of `limit`
+    pub fn within_limit(&self, pid: ProcessId, limit: Limit, extra: u64) -> bool {
+        match self.process_limits(pid).max(limit) {
+            Some(max) => self.process_usage(pid, limit) + extra <= max,
+            None => true,
+        }
+    }
+
+    /// How many more instructions the process `pid` may run, `None` if it isn't limited
+    pub(super) fn instructions_left(&self, pid: ProcessId) -> Option<u64> {
+        let max = self.process_limits(pid).max_instructions?;
+        Some(max.saturating_sub(self.process_usage(pid, Limit::Instructions)))
+    }
+
+    /// Turns a run that used up the last instructions the process may run into a fault, unless
+    /// the task is leaving anyway
+    pub(super) fn enforce_instruction_limit(
+        &self,
+        tid: (TaskId, ProcessId),
+        iterations: u32,
+        res: Result<TaskRunResult, (TaskError, u32)>,
+    ) -> Result<TaskRunResult, (TaskError, u32)> {
+        let ran = match &res {
+            Ok(TaskRunResult::Continue) => iterations,
+            Ok(TaskRunResult::Wait(ran)) | Ok(TaskRunResult::Block(ran, _)) => *ran,
+            _ => return res,
+        };
+        match self.instructions_left(tid.1) {
+            Some(left) if ran as u64 >= left => {
+                let pc = self.tasks.get_task(tid.0).lock().unwrap().vm_state.pc;
+                Err((TaskError::LimitExceeded(Limit::Instructions, pc), ran))
+            }
+            _ => res,
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::system::ProcessExitStatus;
+
+    /// BEQ $zero, $zero, -1 with a NOP in its delay slot, which never ends
+    const SPIN: [u8; 8] = [0xFF, 0xFF, 0x00, 0x10, 0, 0, 0, 0];
+
+    #[test]
+    fn instruction_limit_ends_the_process() {
+        let mut sys = System::builder().build();
+        let limits = ProcessLimits {
+            max_instructions: Some(10_000),
+            ..ProcessLimits::UNLIMITED
+        };
+        sys.add_program_with_limits(&Program::from_raw(&SPIN), limits);
+        let report = sys.run_blocking();
+        let process = &report.processes[0];
+        assert!(matches!(
+            process.status,
+            ProcessExitStatus::Faulted(TaskError::LimitExceeded(Limit::Instructions, _))
+        ));
+        assert_eq!(process.instructions, 10_000);
+    }
+
+    #[test]
+    fn usage_is_checked_against_the_limit() {
+        let mut sys = System::builder().build();
+        let pid = sys.add_program(&Program::from_raw(&SPIN)).to_pid();
+        let pages = sys.process_usage(pid, Limit::Pages);
+        assert!(pages > 0);
+        assert!(sys.within_limit(pid, Limit::Pages, u64::MAX - pages));
+
+        sys.set_process_limits(
+            pid,
+            ProcessLimits {
+                max_pages: Some(pages as usize),
+                max_threads: Some(1),
+                ..ProcessLimits::UNLIMITED
+            },
+        );
+        assert!(sys.within_limit(pid, Limit::Pages, 0));
+        assert!(!sys.within_limit(pid, Limit::Pages, 1));
+        assert!(!sys.within_limit(pid, Limit::Threads, 1));
+        assert!(sys.within_limit(pid, Limit::Handles, 100));
+        assert_eq!(sys.instructions_left(pid), None);
+    }
+}
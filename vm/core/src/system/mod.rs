@@ -1,17 +1,34 @@
+pub mod builder;
+pub mod builtin;
+pub mod fault;
+pub mod limits;
+pub mod report;
+pub mod shared_memory;
 pub mod syscore;
 
-use crate::scheduler::SchedulerTask;
+use crate::scheduler::{SchedulerTask, WaitKey};
 use crate::SystemTime;
 
+pub use builder::*;
+pub use fault::*;
+pub use limits::*;
 use rclite::Arc;
+pub use report::*;
+pub use shared_memory::*;
 pub use syscore::*;
 
 // ------------------------------------------------------------------
 
-use crate::task::{PageVAddressStart, Task, TaskError, TaskMemory, TaskRunResult};
+use crate::loader::Program;
+use crate::task::{
+    DelaySlots, PageVAddressStart, Protection, Task, TaskError, TaskMemory, TaskRunResult,
+    MAIN_STACK_SIZE,
+};
 
-use crate::taskpool::{TaskPool, TaskPoolSharedMemory};
-use crate::util::{Page, ThreadId, TaskId};
+use crate::taskpool::{FillPolicy, TaskPool, TaskPoolSharedMemory};
+use crate::util::{
+    page_address, page_number, page_offset, pages_for, Page, ProcessId, TaskId, PAGE_OFFSET_MASK,
+};
 
 #[derive(Default)]
 pub struct System {
@@ -21,51 +38,148 @@ pub struct System {
 }
 
 impl System {
-    pub fn run_blocking(&mut self) -> u64 {
-        let ll_arc = self.sys_mem.clone();
-        let mut mem = TaskMemory::new(&ll_arc.ll_bit);
+    pub fn run_blocking(&mut self) -> RunReport {
+        let mut mem = TaskMemory::new();
+        mem.written = self.sys_mem.written.clone();
+        let mut report = RunReport::default();
 
         while let Some((mut task, iterations)) = self.core.scheduler.schedule_next_task() {
+            let tid = task.tid();
+            let iterations = match self.instructions_left(tid.1) {
+                Some(left) => iterations.min(left.min(u32::MAX as u64) as u32),
+                None => iterations,
+            };
             let (res, start, end) = self.run_task(&mut task, &mut mem, iterations);
+            let res = self.enforce_instruction_limit(tid, iterations, res);
 
-            let tid = task.tid();
             //self.post_task_stuff();
 
+            let mut block = None;
+            let mut thread_exit_code = None;
+            let mut process_status = None;
             let (iterations, remove) = match res {
                 Ok(ok) => match ok {
                     TaskRunResult::Continue => (iterations, false),
                     TaskRunResult::Wait(actually_ran) => (actually_ran, false),
+                    TaskRunResult::Block(actually_ran, key) => {
+                        block = Some(key);
+                        (actually_ran, false)
+                    }
                     TaskRunResult::Exit(actually_ran, code) => {
                         tracing::info!("Task: {} exited with code: {}", tid.0, code);
 
-                        // if tid == self.tasks.get_tasks(tid.1);
+                        // other threads of the process can join it until the process ends
+                        let process = self.core.processes.get_mut(&tid.1).unwrap();
+                        if !process.detached_threads.remove(&tid.0) {
+                            process.exited_threads.insert(tid.0, code);
+                        }
+                        thread_exit_code = Some(code);
+                        (actually_ran, true)
+                    }
+                    TaskRunResult::ExitProcess(actually_ran, code) => {
+                        tracing::info!("Process: {} exited with code: {}", tid.1, code);
 
+                        self.exit_process(tid.1, tid.0);
+                        process_status = Some(ProcessExitStatus::Exited(code));
                         (actually_ran, true)
                     }
                 },
                 Err((err, ran)) => {
                     tracing::info!(
-                        "Task: {} encountered an error: {:#?}\nDUMP: {:#?}\nTerminating",
+                        "Task: {} encountered an error: {:#?}\nDUMP: {:#?}\nTerminating process: {}",
                         tid.0,
                         err,
-                        self.tasks.get_task(tid.0)
+                        self.tasks.get_task(tid.0),
+                        tid.1
                     );
+                    self.exit_process(tid.1, tid.0);
+                    process_status = Some(ProcessExitStatus::Faulted(err));
                     (ran, true)
                 }
             };
 
+            if let Some(process) = self.core.processes.get_mut(&tid.1) {
+                process.instructions += iterations as u64;
+            }
+
             if remove{
-                self.tasks.remove_task(task.tid().0);
+                let removed = self.tasks.remove_task(task.tid().0);
+                self.thread_removed(tid.1);
+                self.core.scheduler.wake(WaitKey::Join(tid.0), u32::MAX);
+                // the stack and anything else only the task held goes back to the pool
+                let pages = removed.lock().unwrap().memory_mapping.private.take_pages();
+                drop(removed);
+                self.sys_mem.reclaim_pages(pages);
             }
-            self.core.scheduler.scheduled_task_report(
-                if remove { None } else { Some(task) },
-                iterations,
-                start,
-                end,
-            );
+
+            if let Some(code) = thread_exit_code {
+                let process = self.core.processes.get_mut(&tid.1).unwrap();
+                if tid.0 == tid.1 {
+                    process.main_exit_code = Some(code);
+                }
+                // the process ends with its last thread, reporting the main threads exit code
+                if self.tasks.process_tasks(tid.1).is_empty() {
+                    let code = process.main_exit_code.unwrap_or(code);
+                    process_status = Some(ProcessExitStatus::Exited(code));
+                }
+            }
+            if let Some(status) = process_status {
+                self.close_handles(tid.1);
+                self.sys_mem.release_process(tid.1);
+                let process = self.core.processes.remove(&tid.1).unwrap();
+                // other processes can wait for it until one of them takes the exit code
+                let code = match status {
+                    ProcessExitStatus::Exited(code) => Some(code),
+                    _ => None,
+                };
+                self.core.exited_processes.insert(tid.1, code);
+                self.core.scheduler.wake(WaitKey::Process(tid.1), u32::MAX);
+                report.processes.push(process.finish(tid.1, status));
+            }
+
+            let task = match block {
+                Some(key) => {
+                    self.core.scheduler.block_task(task, key);
+                    None
+                }
+                None if remove => None,
+                None => Some(task),
+            };
+            self.core
+                .scheduler
+                .scheduled_task_report(task, iterations, start, end);
         }
 
-        self.core.scheduler.total_iterations()
+        let blocked = self.core.scheduler.blocked_tasks();
+        if blocked > 0 {
+            tracing::warn!("Stopping with {} task(s) still blocked", blocked);
+        }
+        for (pid, process) in self.core.processes.drain() {
+            report
+                .processes
+                .push(process.finish(pid, ProcessExitStatus::Deadlocked));
+        }
+
+        if let Some(written) = &self.sys_mem.written {
+            report.uninitialized_reads = written.take_reads();
+        }
+
+        let pool = self.sys_mem.v_mem.read().unwrap();
+        tracing::info!(
+            "Page pool holds {} page(s), {} free",
+            pool.pages.len(),
+            pool.free_pages()
+        );
+        drop(pool);
+
+        report.total_iterations = self.core.scheduler.total_iterations();
+        report
+    }
+
+    fn thread_removed(&mut self, pid: ProcessId) {
+        if let Some(process) = self.core.processes.get_mut(&pid) {
+            process.threads -= 1;
+        }
     }
 
     fn remove_task(&mut self, task: TaskId) {
@@ -74,38 +188,142 @@ impl System {
         self.core.scheduler.remove_task(task);
     }
 
+    /// Tears down every task of `pid` other than `current`, which is currently running and is
+    /// removed by the caller. Their pages are freed once the last mapping of them is dropped
+    fn exit_process(&mut self, pid: ProcessId, current: TaskId) {
+        for tid in self.tasks.process_tasks(pid) {
+            if tid == current {
+                continue;
+            }
+            self.remove_task(tid);
+            self.thread_removed(pid);
+            self.core.scheduler.wake(WaitKey::Join(tid), u32::MAX);
+        }
+    }
+
     fn add_task(&mut self, task: Task) {
+        self.core
+            .processes
+            .entry(task.thread_id().1)
+            .or_insert_with(ProcessInfo::new)
+            .threads += 1;
         self.core.scheduler.add_task(task.thread_id());
         self.tasks.add_task(task);
     }
 
     pub fn add_task_with_pages(
         &mut self,
-        initial_pages: &[u16],
-        initializer: impl FnOnce(Vec<(Arc<Page>, PageVAddressStart)>),
+        initial_pages: &[PageVAddressStart],
+        initializer: impl FnOnce(Vec<(Arc<Page>, PageVAddressStart, Protection)>),
     ) {
-        let mut task = Task::new_mainthread(self.next_task_id());
+        let task = Task::new_mainthread(self.next_task_id());
 
+        let mut shared = task.memory_mapping.address_space.lock().unwrap();
         for page in initial_pages {
-            task.memory_mapping
-                .mapping
-                .push((self.sys_mem.new_page(), *page));
+            shared.map(
+                self.sys_mem.new_page(task.thread_id().1),
+                *page,
+                Protection::ALL,
+            );
+        }
+        let t = shared
+            .mappings()
+            .map(|(v_page, page, protection)| (page.clone(), v_page, protection))
+            .collect();
+        drop(shared);
+        initializer(t);
+        self.add_task(task);
+    }
+
+    /// Creates a new process from `program`, mapping a page for every page its segments touch
+    /// and a page for the top of its stack. Pages get the protection of their segment, a page
+    /// shared by several segments allows everything any of them allows
+    pub fn add_program(&mut self, program: &Program<'_>) -> TaskId {
+        let mut task = Task::new_mainthread(self.next_task_id());
+
+        for segment in &program.segments {
+            if segment.mem_size == 0 {
+                continue;
+            }
+            let protection = Protection::from_bits(segment.flags);
+            let start = segment.vaddr;
+            let end = segment.vaddr + (segment.mem_size - 1);
+            for v_page in page_number(start)..=page_number(end) {
+                let mut shared = task.memory_mapping.address_space.lock().unwrap();
+                match shared.protection(v_page) {
+                    Some(existing) => {
+                        shared.protect(v_page, existing | protection);
+                    }
+                    None => shared.map(
+                        self.sys_mem.new_page(task.thread_id().1),
+                        v_page,
+                        protection,
+                    ),
+                }
+                let page = shared.page(v_page).unwrap().clone();
+                drop(shared);
+
+                // the part of the segment on this page
+                let first = start.max(page_address(v_page));
+                let last = end.min(page_address(v_page) + PAGE_OFFSET_MASK);
+                let len = (last - first) as usize + 1;
+                let data = segment.data.get((first - start) as usize..).unwrap_or_default();
+                let data = &data[..data.len().min(len)];
+                page.write_bytes(page_offset(first), data);
+                // everything past the file data is .bss and must start zeroed
+                if data.len() < len && self.sys_mem.fill_policy() != FillPolicy::Zero {
+                    page.fill_bytes(page_offset(first) + data.len() as u16, len - data.len(), 0);
+                }
+                self.sys_mem.mark_written(&page, first, len);
+            }
         }
 
-        let mem = self.sys_mem.v_mem.read().unwrap();
-        let mut t = Vec::new();
-        for mapping in &task.memory_mapping.mapping {
-            t.push(mapping.clone());
+        // the main threads stack is private just like the stacks of the threads it starts
+        let stack_top = pages_for(program.sp as u64);
+        let stack = task
+            .memory_mapping
+            .address_space
+            .lock()
+            .unwrap()
+            .allocate_stack_at(stack_top as u32, pages_for(MAIN_STACK_SIZE as u64) as u32);
+        let pid = task.thread_id().1;
+        match stack {
+            Some(stack) => task
+                .memory_mapping
+                .map_stack(stack, || self.sys_mem.new_page(pid)),
+            None => tracing::warn!(
+                "Could not place the stack of task: {} below {:#010X}",
+                task.tid(),
+                program.sp
+            ),
         }
-        drop(mem);
-        initializer(t);
+
+        task.vm_state.pc = program.entry;
+        task.vm_state.reg[28] = program.gp.unwrap_or(0);
+        task.vm_state.reg[29] = program.sp;
+        task.vm_state.reg[30] = program.sp;
+
+        let tid = task.tid();
         self.add_task(task);
+        tid
+    }
+
+    /// Sets how every thread of `pid` runs delay slots, threads it starts later inherit the mode.
+    /// Can't be called while one of them is running
+    pub fn set_delay_slots(&mut self, pid: ProcessId, delay_slots: DelaySlots) {
+        for tid in self.tasks.process_tasks(pid) {
+            self.tasks.get_task(tid).lock().unwrap().delay_slots = delay_slots;
+        }
+    }
+
+    pub(crate) fn page_pool(&self) -> &TaskPoolSharedMemory {
+        &self.sys_mem
     }
 
     fn run_task(
         &mut self,
         scheduler_task: &mut SchedulerTask,
-        mem: &mut TaskMemory<'_, '_>,
+        mem: &mut TaskMemory<'_>,
         iters: u32,
     ) -> (
         Result<TaskRunResult, (TaskError, u32)>,
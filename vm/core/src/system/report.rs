@@ -0,0 +1,91 @@
+use std::{
+    collections::{HashMap, HashSet},
+    time::Duration,
+};
+
+use crate::task::{TaskError, UninitializedRead};
+use crate::util::{ProcessId, TaskId};
+use crate::SystemTime;
+
+use super::ProcessLimits;
+
+#[derive(Debug)]
+pub enum ProcessExitStatus {
+    Exited(u32),
+    /// A thread of the process was killed by an error, which takes the whole process down with it
+    Faulted(TaskError),
+    /// The process still had threads when the system stopped, all of them blocked
+    Deadlocked,
+}
+
+impl ProcessExitStatus {
+    pub fn is_success(&self) -> bool {
+        matches!(self, ProcessExitStatus::Exited(0))
+    }
+}
+
+#[derive(Debug)]
+pub struct ProcessReport {
+    pub pid: ProcessId,
+    pub status: ProcessExitStatus,
+    pub instructions: u64,
+    pub wall_time: Duration,
+}
+
+/// What [`super::System::run_blocking`] returns once every task has stopped
+#[derive(Debug, Default)]
+pub struct RunReport {
+    pub total_iterations: u64,
+    pub processes: Vec<ProcessReport>,
+    /// Reads of never written memory, only tracked when the system was built to track them
+    pub uninitialized_reads: Vec<UninitializedRead>,
+}
+
+impl RunReport {
+    pub fn process(&self, pid: ProcessId) -> Option<&ProcessReport> {
+        self.processes.iter().find(|report| report.pid == pid)
+    }
+}
+
+/// Bookkeeping for a process that is still running
+pub(super) struct ProcessInfo {
+    pub(super) started: SystemTime,
+    pub(super) instructions: u64,
+    /// Exit code of the main thread if it exited before the rest of the process
+    pub(super) main_exit_code: Option<u32>,
+    /// Number of threads of the process that haven't been removed yet
+    pub(super) threads: usize,
+    pub(super) limits: ProcessLimits,
+    /// Ids of the shared memory objects the process has open
+    pub(super) handles: HashSet<u32>,
+    /// Exit codes of threads that exited but haven't been joined yet
+    pub(super) exited_threads: HashMap<TaskId, u32>,
+    /// Threads no one is going to join, their exit codes aren't kept
+    pub(super) detached_threads: HashSet<TaskId>,
+}
+
+impl ProcessInfo {
+    pub(super) fn new() -> Self {
+        Self {
+            started: crate::systime_now(),
+            instructions: 0,
+            main_exit_code: None,
+            threads: 0,
+            limits: ProcessLimits::UNLIMITED,
+            handles: HashSet::new(),
+            exited_threads: HashMap::new(),
+            detached_threads: HashSet::new(),
+        }
+    }
+
+    pub(super) fn finish(self, pid: ProcessId, status: ProcessExitStatus) -> ProcessReport {
+        ProcessReport {
+            pid,
+            status,
+            instructions: self.instructions,
+            wall_time: crate::systime_now()
+                .duration_since(self.started)
+                .unwrap_or_default(),
+        }
+    }
+}
@@ -0,0 +1,236 @@
+//! Named memory that several processes can map at the same time
+
+use std::collections::HashMap;
+
+use rclite::Arc;
+
+use crate::{
+    task::{PageVAddressStart, Protection, Task, TaskMemory},
+    util::{Page, ProcessId},
+};
+
+use super::{Limit, System};
+
+/// Pages that live on until the last handle to the object is closed, no matter which processes
+/// map them
+pub struct SharedMemoryObject {
+    pub name: String,
+    pub pages: Vec<Arc<Page>>,
+    /// Number of processes holding a handle to the object
+    pub handles: usize,
+}
+
+/// Every open shared memory object by id, ids are never reused
+#[derive(Default)]
+pub struct SharedMemoryTable {
+    objects: HashMap<u32, SharedMemoryObject>,
+    names: HashMap<String, u32>,
+    next_id: u32,
+}
+
+impl SharedMemoryTable {
+    pub fn get(&self, id: u32) -> Option<&SharedMemoryObject> {
+        self.objects.get(&id)
+    }
+
+    pub fn find(&self, name: &str) -> Option<u32> {
+        self.names.get(name).copied()
+    }
+
+    fn insert(&mut self, object: SharedMemoryObject) -> u32 {
+        self.next_id += 1;
+        self.names.insert(object.name.clone(), self.next_id);
+        self.objects.insert(self.next_id, object);
+        self.next_id
+    }
+
+    fn remove(&mut self, id: u32) -> Option<SharedMemoryObject> {
+        let object = self.objects.remove(&id)?;
+        self.names.remove(&object.name);
+        Some(object)
+    }
+}
+
+impl System {
+    /// Opens the shared memory object called `name`, creating it with `pages` zeroed pages owned
+    /// by `owner` if it doesn't exist yet. Returns its id and its size in pages, or `None` if the
+    /// limits of `owner` don't allow it
+    pub fn open_shared_memory(
+        &mut self,
+        name: &str,
+        pages: u32,
+        owner: ProcessId,
+    ) -> Option<(u32, u32)> {
+        let existing = self.core.shared_memory.find(name);
+        let opened = existing.is_some_and(|id| self.holds_handle(owner, id));
+        if !opened && !self.within_limit(owner, Limit::Handles, 1) {
+            return None;
+        }
+        if let Some(id) = existing {
+            let object = self.core.shared_memory.get(id)?;
+            let len = object.pages.len() as u32;
+            self.add_handle(owner, id);
+            return Some((id, len));
+        }
+        if pages == 0 || !self.within_limit(owner, Limit::Pages, pages as u64) {
+            return None;
+        }
+        let pages = (0..pages)
+            .map(|_| self.sys_mem.new_zeroed_page(owner))
+            .collect::<Vec<_>>();
+        let len = pages.len() as u32;
+        let id = self.core.shared_memory.insert(SharedMemoryObject {
+            name: name.to_owned(),
+            pages,
+            handles: 0,
+        });
+        self.add_handle(owner, id);
+        Some((id, len))
+    }
+
+    /// Whether the process `pid` opened the shared memory object `id`
+    pub fn holds_handle(&self, pid: ProcessId, id: u32) -> bool {
+        self.core
+            .processes
+            .get(&pid)
+            .is_some_and(|process| process.handles.contains(&id))
+    }
+
+    /// Gives `pid` a handle to the shared memory object `id`, a process holds at most one handle
+    /// to each object
+    pub(super) fn add_handle(&mut self, pid: ProcessId, id: u32) {
+        let Some(process) = self.core.processes.get_mut(&pid) else {
+            return;
+        };
+        if process.handles.insert(id) {
+            if let Some(object) = self.core.shared_memory.objects.get_mut(&id) {
+                object.handles += 1;
+            }
+        }
+    }
+
+    /// Drops a handle to the shared memory object `id`, removing the object with the last one
+    fn handle_closed(&mut self, id: u32) {
+        let Some(object) = self.core.shared_memory.objects.get_mut(&id) else {
+            return;
+        };
+        object.handles -= 1;
+        if object.handles == 0 {
+            let object = self.core.shared_memory.remove(id).unwrap();
+            self.sys_mem.reclaim_pages(object.pages);
+        }
+    }
+
+    /// Closes every handle `pid` still holds, for when the process ends
+    pub(super) fn close_handles(&mut self, pid: ProcessId) {
+        let handles = match self.core.processes.get_mut(&pid) {
+            Some(process) => std::mem::take(&mut process.handles),
+            None => return,
+        };
+        for id in handles {
+            self.handle_closed(id);
+        }
+    }
+
+    /// Maps the shared memory object `id` into the address space of the process of `task`,
+    /// preferably starting at `hint`. Returns the first page of the mapping and its size in pages,
+    /// or `None` if the process hasn't opened the object
+    pub fn map_shared_memory(
+        &mut self,
+        task: &Task,
+        mem: &mut TaskMemory<'_>,
+        id: u32,
+        hint: PageVAddressStart,
+    ) -> Option<(PageVAddressStart, u32)> {
+        if !self.holds_handle(task.thread_id().1, id) {
+            return None;
+        }
+        let object = self.core.shared_memory.get(id)?;
+        let pages = object.pages.len() as u32;
+        if !self.within_limit(task.thread_id().1, Limit::Pages, pages as u64) {
+            return None;
+        }
+
+        let mut address_space = task.memory_mapping.address_space.lock().unwrap();
+        let start = if hint != 0 && address_space.is_free(hint, pages) {
+            hint
+        } else {
+            address_space.find_free(pages)?
+        };
+        let protection = Protection::READ_WRITE | Protection::SHARED;
+        for (v_page, page) in (start..).zip(&object.pages) {
+            // the address space keeps the page alive for longer than this run so the page can be
+            // used right away
+            unsafe { mem.map_page(page, v_page, protection) };
+            address_space.map(page.clone(), v_page, protection);
+        }
+        drop(address_space);
+
+        self.sys_mem
+            .share_pages(object.pages.iter().map(|page| &**page), task.thread_id().1);
+        Some((start, pages))
+    }
+
+    /// Closes the handle `owner` holds to the shared memory object `id`. With the last handle the
+    /// object is removed so it can't be opened or mapped anymore, its pages are freed once every
+    /// process has unmapped them. Returns whether `owner` held a handle
+    pub fn close_shared_memory(&mut self, id: u32, owner: ProcessId) -> bool {
+        let closed = self
+            .core
+            .processes
+            .get_mut(&owner)
+            .is_some_and(|process| process.handles.remove(&id));
+        if closed {
+            self.handle_closed(id);
+        }
+        closed
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{loader::Program, system::builtin::EXIT_PROCESS};
+
+    #[test]
+    fn objects_live_until_the_last_handle_is_closed() {
+        // the scheduler sizes time slices by the ones before, so the processes count down a while
+        // before they exit instead of leaving it with a slice of a single instruction
+        let code = [
+            0x2408_03E8, // ADDIU $t0, $zero, 1000
+            0x2508_FFFF, // ADDIU $t0, $t0, -1
+            0x1500_FFFE, // BNE $t0, $zero, -2
+            0,
+            EXIT_PROCESS << 6 | 0b001100,
+        ];
+        let code: Vec<u8> = code.iter().flat_map(|op: &u32| op.to_le_bytes()).collect();
+        let mut sys = System::builder().build();
+        let first = sys.add_program(&Program::from_raw(&code)).to_pid();
+        let second = sys.add_program(&Program::from_raw(&code)).to_pid();
+
+        let (id, pages) = sys.open_shared_memory("shared", 2, first).unwrap();
+        assert_eq!(
+            sys.open_shared_memory("shared", 0, second),
+            Some((id, pages))
+        );
+        assert!(sys.close_shared_memory(id, first));
+        // only the handle of the caller is gone, the other one keeps the object alive
+        assert!(!sys.close_shared_memory(id, first));
+        assert!(sys.holds_handle(second, id));
+        assert_eq!(sys.core.shared_memory.find("shared"), Some(id));
+        assert!(sys.close_shared_memory(id, second));
+        assert_eq!(sys.core.shared_memory.find("shared"), None);
+
+        // a process that ends closes the handles it still holds
+        let (id, _) = sys.open_shared_memory("shared", 1, first).unwrap();
+        sys.open_shared_memory("shared", 1, second).unwrap();
+        assert!(sys.close_shared_memory(id, first));
+        assert_eq!(
+            sys.core.shared_memory.get(id).map(|object| object.handles),
+            Some(1)
+        );
+        sys.run_blocking();
+        assert!(sys.core.shared_memory.get(id).is_none());
+        assert_eq!(sys.core.shared_memory.find("shared"), None);
+    }
+}
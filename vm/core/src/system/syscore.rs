@@ -1,145 +1,145 @@
-use std::{
-    collections::HashMap,
-    time::{Duration, SystemTime},
-};
+use std::collections::HashMap;
 
 use crate::{
-    scheduler::{Scheduler, SchedulerTask},
+    scheduler::{Scheduler, SchedulerTask, WaitKey},
     task::{Task, TaskError, TaskMemory},
-    util::TaskId,
+    util::{ProcessId, TaskId},
 };
 
-use super::System;
+use super::{PageFaultHandler, ProcessInfo, SharedMemoryTable, System};
 
 #[derive(Default)]
 pub struct SystemCore {
     pub(super) partial_process_output: HashMap<TaskId, String>,
+    pub(super) processes: HashMap<ProcessId, ProcessInfo>,
+    /// Exit codes of processes that ended but weren't waited for yet, `None` if the process faulted
+    pub(super) exited_processes: HashMap<ProcessId, Option<u32>>,
     pub(super) next_task_id: u32,
     pub(super) scheduler: Scheduler,
+    pub(super) system_calls: SystemCallTable,
+    pub(super) page_fault_handler: Option<Box<dyn PageFaultHandler>>,
+    pub(super) shared_memory: SharedMemoryTable,
+}
+
+/// A host side implementation of a system call. Any `FnMut` with the same signature as
+/// [`SystemCallHandler::system_call`] is a handler
+pub trait SystemCallHandler {
+    fn system_call(
+        &mut self,
+        sys: &mut System,
+        task: &mut Task,
+        scheduler_task: &mut SchedulerTask,
+        mem: &mut TaskMemory<'_>,
+    ) -> InterfaceCallResult;
+}
+
+impl<F> SystemCallHandler for F
+where
+    F: FnMut(
+        &mut System,
+        &mut Task,
+        &mut SchedulerTask,
+        &mut TaskMemory<'_>,
+    ) -> InterfaceCallResult,
+{
+    fn system_call(
+        &mut self,
+        sys: &mut System,
+        task: &mut Task,
+        scheduler_task: &mut SchedulerTask,
+        mem: &mut TaskMemory<'_>,
+    ) -> InterfaceCallResult {
+        self(sys, task, scheduler_task, mem)
+    }
+}
+
+/// Maps system call ids to their handlers, starts out with the built in calls registered
+pub struct SystemCallTable {
+    handlers: HashMap<u32, Box<dyn SystemCallHandler>>,
+    /// Ids whose handler is running, with whether the id was registered or unregistered since
+    running: HashMap<u32, bool>,
+}
+
+impl Default for SystemCallTable {
+    fn default() -> Self {
+        let mut table = Self {
+            handlers: HashMap::new(),
+            running: HashMap::new(),
+        };
+        super::builtin::register(&mut table);
+        table
+    }
+}
+
+impl SystemCallTable {
+    /// Registers `handler` for `id`, returning the handler it replaced if there was one
+    pub fn register(
+        &mut self,
+        id: u32,
+        handler: impl SystemCallHandler + 'static,
+    ) -> Option<Box<dyn SystemCallHandler>> {
+        self.touch(id);
+        self.handlers.insert(id, Box::new(handler))
+    }
+
+    pub fn unregister(&mut self, id: u32) -> Option<Box<dyn SystemCallHandler>> {
+        self.touch(id);
+        self.handlers.remove(&id)
+    }
+
+    pub fn is_registered(&self, id: u32) -> bool {
+        self.handlers.contains_key(&id)
+    }
+
+    fn touch(&mut self, id: u32) {
+        if let Some(touched) = self.running.get_mut(&id) {
+            *touched = true;
+        }
+    }
+
+    /// Takes the handler for `id` out of the table while it runs
+    fn start_call(&mut self, id: u32) -> Option<Box<dyn SystemCallHandler>> {
+        let handler = self.handlers.remove(&id)?;
+        self.running.insert(id, false);
+        Some(handler)
+    }
+
+    /// Puts the handler back once it ran, unless it replaced or removed itself
+    fn finish_call(&mut self, id: u32, handler: Box<dyn SystemCallHandler>) {
+        if self.running.remove(&id) == Some(false) {
+            self.handlers.insert(id, handler);
+        }
+    }
 }
 
 impl System {
+    /// Registers `handler` for the system call `id`, replacing the built in call if there is one
+    pub fn register_system_call(
+        &mut self,
+        id: u32,
+        handler: impl SystemCallHandler + 'static,
+    ) -> Option<Box<dyn SystemCallHandler>> {
+        self.core.system_calls.register(id, handler)
+    }
+
+    pub fn unregister_system_call(&mut self, id: u32) -> Option<Box<dyn SystemCallHandler>> {
+        self.core.system_calls.unregister(id)
+    }
+
     pub fn system_call(
         &mut self,
         id: u32,
         task: &mut Task,
         scheduler_task: &mut SchedulerTask,
-        mem: &mut TaskMemory<'_, '_>,
+        mem: &mut TaskMemory<'_>,
     ) -> InterfaceCallResult {
-        match id {
-            0 => return InterfaceCallResult::Exit,
-            1 => {
-                tracing::info!("Task: {} -> {}", task.tid(), task.vm_state.reg[4] as i32);
-            }
-            4 => {
-                let mut address = task.vm_state.reg[4];
-                let mut str: Vec<u8> = Vec::new();
-                loop {
-                    match mem.mem.get(address as usize >> 16).unwrap() {
-                        Some(page) => {
-                            let char = page.get_u8(address as u16);
-                            if char == 0 {
-                                break;
-                            }
-                            str.push(char);
-                            address += 1;
-                        }
-                        None => {
-                            return InterfaceCallResult::ImmediateKill(Some(
-                                TaskError::MemoryDoesNotExistError(address, task.vm_state.pc),
-                            ))
-                        }
-                    }
-                }
-
-                let str = String::from_utf8(str);
-                if let Ok(str) = str {
-                    tracing::info!("Task: {} -> {}", task.tid(), str);
-                } else {
-                    return InterfaceCallResult::MalformedCallArgs;
-                }
-            }
-
-            5 => {
-                let char = task.vm_state.reg[4] as u8 as char;
-                if char != '\n' {
-                    if let std::collections::hash_map::Entry::Vacant(e) =
-                        self.core.partial_process_output.entry(task.tid())
-                    {
-                        e.insert(char.into());
-                    } else {
-                        self.core
-                            .partial_process_output
-                            .get_mut(&task.tid())
-                            .unwrap()
-                            .push(char);
-                    }
-                } else if let Some(msg) = self.core.partial_process_output.remove(&task.tid()) {
-                    tracing::info!("Task: {} -> {}", task.tid(), msg);
-                } else {
-                    tracing::info!("Task: {} -> ", task.tid());
-                }
-            }
-            60 => {
-                let time = crate::systime_now()
-                    .duration_since(SystemTime::UNIX_EPOCH)
-                    .unwrap();
-                let dur = time.as_nanos() as u64;
-                task.vm_state.reg[2] = dur as u32;
-                task.vm_state.reg[3] = (dur >> 32) as u32;
-            }
-            100 => {
-                let mut new_task = Task::new_subthread(task.thread_id().1, self.next_task_id());
-
-                let shared_core_and_data = task.memory_mapping.mapping.first().unwrap().clone();
-
-                new_task.memory_mapping.mapping.push(shared_core_and_data);
-
-                // tasks default stack
-                new_task
-                    .memory_mapping
-                    .mapping
-                    .push((self.sys_mem.new_page(), 0x7FFF));
-
-                new_task.vm_state.pc = task.vm_state.reg[4];
-                new_task.vm_state.reg[4] = task.vm_state.reg[5];
-                new_task.vm_state.reg[29] = 0x80000000; //start of stack
-                new_task.vm_state.reg[31] = 0xFFFFFFFF;
-
-                tracing::info!(
-                    "Created new task: {}\nDUMP{{:?}}",
-                    new_task.tid(),
-                    //new_task
-                );
-                task.vm_state.reg[2] = new_task.tid().into_raw();
-                self.add_task(new_task);
-            }
-            101 => {
-                let val = task.vm_state.reg[4] as u64 | ((task.vm_state.reg[5] as u64) << 32);
-                let dur = Duration::from_nanos(val);
-                scheduler_task.sleep_for = Some(dur);
-                //23479387.80014355
-                //248832255.48680574
-                //233960039
-                return InterfaceCallResult::Wait;
-            }
-            102 => {
-                // stop doing tings and stuff and
-                return InterfaceCallResult::Wait;
-            }
-            // Futex wake
-            200 => {
-                let futex_addr = task.vm_state.reg[4];
-                let tasks_to_wake = task.vm_state.reg[5];
-            }
-            // Futex wait
-            201 => {
-                let futex_addr = task.vm_state.reg[4];
-                let condition = task.vm_state.reg[5];
-            }
-            _ => return InterfaceCallResult::InvalidCall(id),
-        }
-        InterfaceCallResult::Continue
+        // the handler is taken out of the table while it runs so it can be given the whole system
+        let Some(mut handler) = self.core.system_calls.start_call(id) else {
+            return InterfaceCallResult::InvalidCall(id);
+        };
+        let res = handler.system_call(self, task, scheduler_task, mem);
+        self.core.system_calls.finish_call(id, handler);
+        res
     }
 
     pub fn next_task_id(&mut self) -> TaskId {
@@ -152,7 +152,7 @@ impl System {
         id: u32,
         _task: &mut Task,
         _scheduler_task: &mut SchedulerTask,
-        _mem: &TaskMemory<'_, '_>,
+        _mem: &TaskMemory<'_>,
     ) -> InterfaceCallResult {
         match id {
             534 => {}
@@ -162,6 +162,12 @@ impl System {
     }
 }
 
+impl From<TaskError> for InterfaceCallResult {
+    fn from(err: TaskError) -> Self {
+        InterfaceCallResult::ImmediateKill(Some(err))
+    }
+}
+
 pub enum InterfaceCallResult {
     Continue,
     ImmediateKill(Option<TaskError>),
@@ -169,5 +175,88 @@ pub enum InterfaceCallResult {
     InvalidCall(u32),
     WaitRepeated,
     Wait,
-    Exit,
+    /// Park the task until something wakes the given key
+    Block(WaitKey),
+    /// Park the task and re-run the call once it is woken
+    BlockRepeated(WaitKey),
+    /// Exit only the calling thread with the given code
+    Exit(u32),
+    /// Exit every thread in the calling process with the given code
+    ExitProcess(u32),
+}
+
+#[cfg(test)]
+mod tests {
+    use std::sync::{
+        atomic::{AtomicBool, Ordering},
+        Arc,
+    };
+
+    use super::*;
+
+    const ID: u32 = 0xF000;
+
+    /// A handler that records when the table drops it
+    struct Handler(Arc<AtomicBool>);
+
+    impl SystemCallHandler for Handler {
+        fn system_call(
+            &mut self,
+            _sys: &mut System,
+            _task: &mut Task,
+            _scheduler_task: &mut SchedulerTask,
+            _mem: &mut TaskMemory<'_>,
+        ) -> InterfaceCallResult {
+            InterfaceCallResult::Continue
+        }
+    }
+
+    impl Drop for Handler {
+        fn drop(&mut self) {
+            self.0.store(true, Ordering::Relaxed);
+        }
+    }
+
+    fn handler() -> (Handler, Arc<AtomicBool>) {
+        let dropped = Arc::new(AtomicBool::new(false));
+        (Handler(dropped.clone()), dropped)
+    }
+
+    #[test]
+    fn handler_is_put_back_after_a_call() {
+        let mut table = SystemCallTable::default();
+        let (first, dropped) = handler();
+        table.register(ID, first);
+        let running = table.start_call(ID).unwrap();
+        assert!(!table.is_registered(ID));
+        table.finish_call(ID, running);
+        assert!(table.is_registered(ID));
+        assert!(!dropped.load(Ordering::Relaxed));
+    }
+
+    #[test]
+    fn handler_that_unregistered_itself_stays_removed() {
+        let mut table = SystemCallTable::default();
+        let (first, dropped) = handler();
+        table.register(ID, first);
+        let running = table.start_call(ID).unwrap();
+        assert!(table.unregister(ID).is_none());
+        table.finish_call(ID, running);
+        assert!(!table.is_registered(ID));
+        assert!(dropped.load(Ordering::Relaxed));
+    }
+
+    #[test]
+    fn handler_that_replaced_itself_keeps_the_replacement() {
+        let mut table = SystemCallTable::default();
+        let (first, dropped) = handler();
+        let (replacement, replacement_dropped) = handler();
+        table.register(ID, first);
+        let running = table.start_call(ID).unwrap();
+        table.register(ID, replacement);
+        table.finish_call(ID, running);
+        assert!(table.is_registered(ID));
+        assert!(dropped.load(Ordering::Relaxed));
+        assert!(!replacement_dropped.load(Ordering::Relaxed));
+    }
 }
@@ -0,0 +1,228 @@
+//! Coprocessor 0, just enough of it for a guest to handle its own faults. A task that set EBase
+//! gets its faults delivered to EBase + 0x180 like a general exception on MIPS instead of being
+//! killed, with EPC, Cause and BadVAddr describing the fault. ERET returns to EPC
+//!
+//! Errors of system calls and breakpoints stay with the host and faults while a handler runs,
+//! with EXL set, still kill the task
+
+use super::{MemoryAccess, TaskError, VmInstructionAddress, VmPtr};
+
+/// Offset of the general exception vector from EBase
+pub const EXCEPTION_VECTOR_OFFSET: u32 = 0x180;
+
+/// The exception codes of the Cause register for the faults a guest can handle
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum ExceptionCode {
+    /// Store to a page that isn't writable
+    Modify = 1,
+    /// Load or fetch from an unmapped page or one that doesn't allow it
+    TlbLoad = 2,
+    /// Store to an unmapped page
+    TlbStore = 3,
+    /// Unaligned load or fetch
+    AddressLoad = 4,
+    /// Unaligned store
+    AddressStore = 5,
+    ReservedInstruction = 10,
+    Overflow = 12,
+    /// A conditional trap instruction trapped
+    Trap = 13,
+    FloatingPoint = 15,
+}
+
+const STATUS_EXL: u32 = 1 << 1;
+const CAUSE_BRANCH_DELAY: u32 = 1 << 31;
+const CAUSE_CODE_SHIFT: u32 = 2;
+/// EBase only holds page aligned addresses in kseg0 and kseg1 on hardware, here any page aligned
+/// address works
+const EBASE_WRITABLE: u32 = 0xFFFFF000;
+
+#[derive(Debug, Default, Clone)]
+pub struct Cop0State {
+    pub bad_vaddr: VmPtr,
+    /// Only EXL, set while an exception is handled, is implemented
+    pub status: u32,
+    pub cause: u32,
+    pub epc: VmInstructionAddress,
+    /// Base of the exception vectors, 0 while the guest hasn't installed any
+    pub ebase: u32,
+}
+
+impl Cop0State {
+    /// MFC0, `None` for registers that don't exist
+    pub fn read(&self, reg: usize, select: u32) -> Option<u32> {
+        match (reg, select) {
+            (8, 0) => Some(self.bad_vaddr),
+            (12, 0) => Some(self.status),
+            (13, 0) => Some(self.cause),
+            (14, 0) => Some(self.epc),
+            (15, 1) => Some(self.ebase),
+            _ => None,
+        }
+    }
+
+    /// MTC0, `None` for registers that don't exist. BadVAddr and Cause can't be written
+    pub fn write(&mut self, reg: usize, select: u32, value: u32) -> Option<()> {
+        match (reg, select) {
+            (8, 0) | (13, 0) => {}
+            (12, 0) => self.status = value & STATUS_EXL,
+            (14, 0) => self.epc = value,
+            (15, 1) => self.ebase = value & EBASE_WRITABLE,
+            _ => return None,
+        }
+        Some(())
+    }
+
+    /// ERET, leaves the exception handler and returns where it should continue
+    pub fn eret(&mut self) -> VmInstructionAddress {
+        self.status &= !STATUS_EXL;
+        self.epc
+    }
+
+    /// Whether a fault now would go to the guest
+    pub fn handles_exceptions(&self) -> bool {
+        self.ebase != 0 && self.status & STATUS_EXL == 0
+    }
+
+    /// Enters the exception handler for the instruction at `epc`, returning the handlers address
+    pub fn raise(
+        &mut self,
+        code: ExceptionCode,
+        epc: VmInstructionAddress,
+        in_delay_slot: bool,
+        bad_vaddr: Option<VmPtr>,
+    ) -> VmInstructionAddress {
+        self.epc = epc;
+        self.cause = (code as u32) << CAUSE_CODE_SHIFT;
+        if in_delay_slot {
+            self.cause |= CAUSE_BRANCH_DELAY;
+        }
+        if let Some(bad_vaddr) = bad_vaddr {
+            self.bad_vaddr = bad_vaddr;
+        }
+        self.status |= STATUS_EXL;
+        self.ebase.wrapping_add(EXCEPTION_VECTOR_OFFSET)
+    }
+}
+
+/// How an error raised by an instruction is delivered to the guest
+pub struct Fault {
+    pub code: ExceptionCode,
+    pub bad_vaddr: Option<VmPtr>,
+}
+
+impl Fault {
+    /// Works out the exception for `err`, `None` if the guest doesn't get to handle it. Division
+    /// by zero isn't an exception on MIPS, it is delivered as the trap compilers put in front of a
+    /// division to check for it
+    pub fn of(err: &TaskError) -> Option<Self> {
+        use ExceptionCode::*;
+        let (code, bad_vaddr) = match *err {
+            TaskError::MemoryDoesNotExistError(access, address, _)
+            | TaskError::StackOverflow(access, address, _) => {
+                let code = match access {
+                    MemoryAccess::Write => TlbStore,
+                    MemoryAccess::Read | MemoryAccess::Execute => TlbLoad,
+                };
+                (code, Some(address))
+            }
+            TaskError::ProtectionFault(MemoryAccess::Write, address, _) => (Modify, Some(address)),
+            TaskError::ProtectionFault(_, address, _) => (TlbLoad, Some(address)),
+            TaskError::MemoryAllignmentError(_, access, address, _) => {
+                let code = match access {
+                    MemoryAccess::Write => AddressStore,
+                    MemoryAccess::Read | MemoryAccess::Execute => AddressLoad,
+                };
+                (code, Some(address))
+            }
+            TaskError::InvalidOperation(..) => (ReservedInstruction, None),
+            TaskError::OverflowError(_) => (Overflow, None),
+            TaskError::Trap(..) | TaskError::DivByZeroError(_) => (Trap, None),
+            TaskError::FloatingPointException(..) => (FloatingPoint, None),
+            TaskError::LimitExceeded(..) => return None,
+        };
+        Some(Fault { code, bad_vaddr })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::system::Limit;
+
+    #[test]
+    fn registers_keep_only_their_writable_bits() {
+        let mut cop0 = Cop0State::default();
+        assert_eq!(cop0.write(12, 0, u32::MAX), Some(()));
+        assert_eq!(cop0.read(12, 0), Some(STATUS_EXL));
+        cop0.write(15, 1, 0x8000_0123).unwrap();
+        assert_eq!(cop0.read(15, 1), Some(0x8000_0000));
+        // writes to BadVAddr and Cause are ignored
+        cop0.write(8, 0, 5).unwrap();
+        cop0.write(13, 0, 5).unwrap();
+        assert_eq!((cop0.read(8, 0), cop0.read(13, 0)), (Some(0), Some(0)));
+        assert_eq!(cop0.read(15, 0), None);
+        assert_eq!(cop0.write(9, 0, 0), None);
+    }
+
+    #[test]
+    fn raise_enters_the_handler_until_eret() {
+        let mut cop0 = Cop0State::default();
+        assert!(!cop0.handles_exceptions());
+        cop0.write(15, 1, 0x0040_1000).unwrap();
+        assert!(cop0.handles_exceptions());
+
+        let handler = cop0.raise(ExceptionCode::TlbStore, 0x400100, true, Some(0x1234));
+        assert_eq!(handler, 0x0040_1000 + EXCEPTION_VECTOR_OFFSET);
+        assert_eq!(cop0.read(13, 0), Some(CAUSE_BRANCH_DELAY | 3 << 2));
+        assert_eq!((cop0.epc, cop0.bad_vaddr), (0x400100, 0x1234));
+        // a fault inside the handler isn't delivered
+        assert!(!cop0.handles_exceptions());
+
+        assert_eq!(cop0.eret(), 0x400100);
+        assert!(cop0.handles_exceptions());
+        // faults without an address keep the last BadVAddr
+        cop0.raise(ExceptionCode::Trap, 0x400200, false, None);
+        assert_eq!(cop0.read(13, 0), Some(13 << 2));
+        assert_eq!(cop0.bad_vaddr, 0x1234);
+    }
+
+    #[test]
+    fn faults_use_the_access_kind() {
+        let code = |err: TaskError| Fault::of(&err).map(|fault| (fault.code, fault.bad_vaddr));
+        use ExceptionCode::*;
+        use MemoryAccess::*;
+        assert_eq!(
+            code(TaskError::MemoryDoesNotExistError(Write, 8, 4)),
+            Some((TlbStore, Some(8)))
+        );
+        assert_eq!(
+            code(TaskError::MemoryDoesNotExistError(Execute, 8, 8)),
+            Some((TlbLoad, Some(8)))
+        );
+        assert_eq!(
+            code(TaskError::StackOverflow(Read, 8, 4)),
+            Some((TlbLoad, Some(8)))
+        );
+        assert_eq!(
+            code(TaskError::ProtectionFault(Write, 8, 4)),
+            Some((Modify, Some(8)))
+        );
+        assert_eq!(
+            code(TaskError::ProtectionFault(Execute, 8, 8)),
+            Some((TlbLoad, Some(8)))
+        );
+        assert_eq!(
+            code(TaskError::MemoryAllignmentError(4, Write, 9, 4)),
+            Some((AddressStore, Some(9)))
+        );
+        assert_eq!(
+            code(TaskError::MemoryAllignmentError(4, Read, 9, 4)),
+            Some((AddressLoad, Some(9)))
+        );
+        assert_eq!(code(TaskError::OverflowError(4)), Some((Overflow, None)));
+        assert_eq!(code(TaskError::Trap(0, 4)), Some((Trap, None)));
+        assert_eq!(code(TaskError::DivByZeroError(4)), Some((Trap, None)));
+        assert_eq!(code(TaskError::LimitExceeded(Limit::Instructions, 4)), None);
+    }
+}
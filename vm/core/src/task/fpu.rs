@@ -0,0 +1,941 @@
+//! Coprocessor 1, the floating point unit. Registers are 32 bits wide and a double lives in an
+//! even/odd register pair like on a MIPS32 FPU with FR=0. NaNs use the legacy MIPS encoding where
+//! a set top fraction bit marks a signaling NaN, operations that produce a NaN always produce the
+//! default NaN
+
+use std::{
+    cmp::Ordering,
+    ops::{Add, Div, Mul, Neg, Sub},
+};
+
+/// Exceptions raised by a floating point operation, the bits match the cause field of the FCSR
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub struct FpuExceptions(u8);
+
+impl FpuExceptions {
+    pub const NONE: Self = Self(0);
+    pub const INEXACT: Self = Self(0b000001);
+    pub const UNDERFLOW: Self = Self(0b000010);
+    pub const OVERFLOW: Self = Self(0b000100);
+    pub const DIVIDE_BY_ZERO: Self = Self(0b001000);
+    pub const INVALID: Self = Self(0b010000);
+    /// Only ever a cause, it can't be disabled and has no flag
+    pub const UNIMPLEMENTED: Self = Self(0b100000);
+
+    /// Takes the lowest six bits of `bits`, anything else is ignored
+    pub const fn from_bits(bits: u32) -> Self {
+        Self(bits as u8 & 0b111111)
+    }
+
+    pub const fn bits(self) -> u32 {
+        self.0 as u32
+    }
+
+    pub const fn union(self, other: Self) -> Self {
+        Self(self.0 | other.0)
+    }
+
+    pub const fn intersects(self, other: Self) -> bool {
+        self.0 & other.0 != 0
+    }
+
+    pub const fn is_empty(self) -> bool {
+        self.0 == 0
+    }
+}
+
+impl std::ops::BitOr for FpuExceptions {
+    type Output = Self;
+
+    fn bitor(self, rhs: Self) -> Self::Output {
+        self.union(rhs)
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum RoundingMode {
+    Nearest,
+    TowardZero,
+    TowardPositive,
+    TowardNegative,
+}
+
+/// Why a coprocessor 1 instruction didn't complete
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Cop1Error {
+    /// The instruction raised exceptions that are enabled in the FCSR, nothing was written
+    Exception(FpuExceptions),
+    /// The instruction or one of its registers isn't valid
+    Reserved,
+}
+
+const FCSR_ROUNDING: u32 = 0b11;
+const FCSR_FLAGS_SHIFT: u32 = 2;
+const FCSR_ENABLES_SHIFT: u32 = 7;
+const FCSR_CAUSE_SHIFT: u32 = 12;
+const FCSR_FLUSH_TO_ZERO: u32 = 1 << 24;
+/// Every bit of the FCSR that can be written, the rest reads as zero
+const FCSR_WRITABLE: u32 = 0xFF83FFFF;
+
+/// Value of the implementation register, single, double and word formats are supported
+const FIR: u32 = (1 << 16) | (1 << 17) | (1 << 20);
+
+/// The integer a conversion to a word produces when the value doesn't fit
+const INVALID_WORD: u32 = 0x7FFFFFFF;
+
+#[derive(Debug, Default, Clone)]
+pub struct FpuState {
+    pub fpr: [u32; 32],
+    /// Control and status register holding the rounding mode, the exception flags, enables and
+    /// causes and the condition codes
+    pub fcsr: u32,
+}
+
+impl FpuState {
+    pub fn rounding_mode(&self) -> RoundingMode {
+        match self.fcsr & FCSR_ROUNDING {
+            0 => RoundingMode::Nearest,
+            1 => RoundingMode::TowardZero,
+            2 => RoundingMode::TowardPositive,
+            _ => RoundingMode::TowardNegative,
+        }
+    }
+
+    fn condition_bit(cc: u32) -> u32 {
+        if cc == 0 {
+            1 << 23
+        } else {
+            1 << (24 + cc)
+        }
+    }
+
+    /// The condition code `cc` as set by the last compare writing it
+    pub fn condition(&self, cc: u32) -> bool {
+        self.fcsr & Self::condition_bit(cc) != 0
+    }
+
+    fn set_condition(&mut self, cc: u32, value: bool) {
+        if value {
+            self.fcsr |= Self::condition_bit(cc);
+        } else {
+            self.fcsr &= !Self::condition_bit(cc);
+        }
+    }
+
+    fn condition_codes(&self) -> u32 {
+        (0..8).fold(0, |codes, cc| codes | ((self.condition(cc) as u32) << cc))
+    }
+
+    pub fn single(&self, reg: usize) -> f32 {
+        f32::from_bits(self.fpr[reg])
+    }
+
+    pub fn set_single(&mut self, reg: usize, value: f32) {
+        self.fpr[reg] = value.to_bits();
+    }
+
+    /// The double held by the register pair starting at `reg`, `None` if `reg` is odd
+    pub fn double(&self, reg: usize) -> Option<f64> {
+        if reg & 1 != 0 {
+            return None;
+        }
+        Some(f64::from_bits(
+            (self.fpr[reg + 1] as u64) << 32 | self.fpr[reg] as u64,
+        ))
+    }
+
+    /// Sets the register pair starting at `reg`, `reg` must be even
+    pub fn set_double(&mut self, reg: usize, value: f64) {
+        let bits = value.to_bits();
+        self.fpr[reg] = bits as u32;
+        self.fpr[reg + 1] = (bits >> 32) as u32;
+    }
+
+    pub fn read_control(&self, reg: usize) -> Option<u32> {
+        let cause_and_flags = (0b111111 << FCSR_CAUSE_SHIFT) | (0b11111 << FCSR_FLAGS_SHIFT);
+        match reg {
+            0 => Some(FIR),
+            25 => Some(self.condition_codes()),
+            26 => Some(self.fcsr & cause_and_flags),
+            28 => Some(
+                self.fcsr & ((0b11111 << FCSR_ENABLES_SHIFT) | FCSR_ROUNDING)
+                    | ((self.fcsr & FCSR_FLUSH_TO_ZERO != 0) as u32) << 2,
+            ),
+            31 => Some(self.fcsr),
+            _ => None,
+        }
+    }
+
+    /// Writes a control register. Like on hardware, enabling an exception whose cause bit is set
+    /// traps right away
+    pub fn write_control(&mut self, reg: usize, value: u32) -> Result<(), Cop1Error> {
+        let cause_and_flags = (0b111111 << FCSR_CAUSE_SHIFT) | (0b11111 << FCSR_FLAGS_SHIFT);
+        match reg {
+            25 => (0..8).for_each(|cc| self.set_condition(cc, value & (1 << cc) != 0)),
+            26 => self.fcsr = self.fcsr & !cause_and_flags | value & cause_and_flags,
+            28 => {
+                let enables = (0b11111 << FCSR_ENABLES_SHIFT) | FCSR_ROUNDING;
+                self.fcsr = self.fcsr & !(enables | FCSR_FLUSH_TO_ZERO)
+                    | value & enables
+                    | if value & 0b100 != 0 {
+                        FCSR_FLUSH_TO_ZERO
+                    } else {
+                        0
+                    };
+            }
+            31 => self.fcsr = value & FCSR_WRITABLE,
+            _ => return Err(Cop1Error::Reserved),
+        }
+        let cause = FpuExceptions::from_bits(self.fcsr >> FCSR_CAUSE_SHIFT);
+        if cause.intersects(self.trapping()) {
+            return Err(Cop1Error::Exception(cause));
+        }
+        Ok(())
+    }
+
+    /// The exceptions that trap instead of setting their flag
+    fn trapping(&self) -> FpuExceptions {
+        FpuExceptions::from_bits(self.fcsr >> FCSR_ENABLES_SHIFT & 0b11111)
+            | FpuExceptions::UNIMPLEMENTED
+    }
+
+    /// Runs a COP1 instruction other than the branches on condition codes, `reg` are the general
+    /// purpose registers for moves and conditional moves
+    pub fn execute(&mut self, op: u32, reg: &mut [u32; 32]) -> Result<(), Cop1Error> {
+        let ft = ((op >> 16) & 0b11111) as usize;
+        let fs = ((op >> 11) & 0b11111) as usize;
+        match (op >> 21) & 0b11111 {
+            0b00000 => {
+                //MFC1
+                reg[ft] = self.fpr[fs];
+            }
+            0b00010 => {
+                //CFC1
+                reg[ft] = self.read_control(fs).ok_or(Cop1Error::Reserved)?;
+            }
+            0b00011 => {
+                //MFHC1
+                if fs & 1 != 0 {
+                    return Err(Cop1Error::Reserved);
+                }
+                reg[ft] = self.fpr[fs + 1];
+            }
+            0b00100 => {
+                //MTC1
+                self.fpr[fs] = reg[ft];
+            }
+            0b00110 => {
+                //CTC1
+                self.write_control(fs, reg[ft])?;
+            }
+            0b00111 => {
+                //MTHC1
+                if fs & 1 != 0 {
+                    return Err(Cop1Error::Reserved);
+                }
+                self.fpr[fs + 1] = reg[ft];
+            }
+            0b10000 => self.format::<f32>(op, reg)?,
+            0b10001 => self.format::<f64>(op, reg)?,
+            0b10100 => self.word_format(op)?,
+            _ => return Err(Cop1Error::Reserved),
+        }
+        Ok(())
+    }
+
+    /// Instructions on singles or doubles
+    fn format<F: Float>(&mut self, op: u32, reg: &[u32; 32]) -> Result<(), Cop1Error> {
+        let ft = ((op >> 16) & 0b11111) as usize;
+        let fs = ((op >> 11) & 0b11111) as usize;
+        let fd = ((op >> 6) & 0b11111) as usize;
+        let rm = self.rounding_mode();
+        let a = F::read(self, fs).ok_or(Cop1Error::Reserved)?;
+        let b = || F::read(self, ft).ok_or(Cop1Error::Reserved);
+
+        let (output, raised) = match op & 0b111111 {
+            //arithmatic
+            0b000000 => {
+                //ADD
+                let (value, raised) = add(rm, a, b()?);
+                (value.output(fd), raised)
+            }
+            0b000001 => {
+                //SUB
+                let (value, raised) = add(rm, a, -b()?);
+                (value.output(fd), raised)
+            }
+            0b000010 => {
+                //MUL
+                let (value, raised) = mul(rm, a, b()?);
+                (value.output(fd), raised)
+            }
+            0b000011 => {
+                //DIV
+                let (value, raised) = div(rm, a, b()?);
+                (value.output(fd), raised)
+            }
+            0b000100 => {
+                //SQRT
+                let (value, raised) = sqrt(rm, a);
+                (value.output(fd), raised)
+            }
+            0b000101 => {
+                //ABS
+                let (value, raised) = sign_op(a, a.abs());
+                (value.output(fd), raised)
+            }
+            0b000111 => {
+                //NEG
+                let (value, raised) = sign_op(a, -a);
+                (value.output(fd), raised)
+            }
+
+            //moves, these never raise exceptions
+            0b000110 => {
+                //MOV
+                return a.output(fd).write(self);
+            }
+            0b010001 => {
+                //MOVF MOVT
+                let cc = (op >> 18) & 0b111;
+                if self.condition(cc) == (op & (1 << 16) != 0) {
+                    a.output(fd).write(self)?;
+                }
+                return Ok(());
+            }
+            0b010010 => {
+                //MOVZ
+                if reg[ft] == 0 {
+                    a.output(fd).write(self)?;
+                }
+                return Ok(());
+            }
+            0b010011 => {
+                //MOVN
+                if reg[ft] != 0 {
+                    a.output(fd).write(self)?;
+                }
+                return Ok(());
+            }
+
+            //conversions
+            0b001100 => {
+                //ROUND.W
+                to_word(RoundingMode::Nearest, a, fd)
+            }
+            0b001101 => {
+                //TRUNC.W
+                to_word(RoundingMode::TowardZero, a, fd)
+            }
+            0b001110 => {
+                //CEIL.W
+                to_word(RoundingMode::TowardPositive, a, fd)
+            }
+            0b001111 => {
+                //FLOOR.W
+                to_word(RoundingMode::TowardNegative, a, fd)
+            }
+            0b100100 => {
+                //CVT.W
+                to_word(rm, a, fd)
+            }
+            0b100000 => {
+                //CVT.S
+                let (value, raised) = a.to_single(rm).ok_or(Cop1Error::Reserved)?;
+                (value.output(fd), raised)
+            }
+            0b100001 => {
+                //CVT.D
+                let (value, raised) = a.to_double().ok_or(Cop1Error::Reserved)?;
+                (value.output(fd), raised)
+            }
+
+            //C.cond
+            cond @ 0b110000..=0b111111 => {
+                let (value, raised) = compare(cond, a, b()?);
+                (Output::Condition((op >> 8) & 0b111, value), raised)
+            }
+
+            _ => return Err(Cop1Error::Reserved),
+        };
+        self.complete(output, raised)
+    }
+
+    /// Instructions on words held in floating point registers
+    fn word_format(&mut self, op: u32) -> Result<(), Cop1Error> {
+        let fs = ((op >> 11) & 0b11111) as usize;
+        let fd = ((op >> 6) & 0b11111) as usize;
+        let word = self.fpr[fs] as i32;
+        let (output, raised) = match op & 0b111111 {
+            0b100000 => {
+                //CVT.S.W
+                let value = word as f32;
+                let error = (word as f64).total_cmp(&(value as f64));
+                let (value, raised) = finish(self.rounding_mode(), value, error);
+                (value.output(fd), raised)
+            }
+            0b100001 => {
+                //CVT.D.W
+                ((word as f64).output(fd), FpuExceptions::NONE)
+            }
+            _ => return Err(Cop1Error::Reserved),
+        };
+        self.complete(output, raised)
+    }
+
+    /// Records the exceptions of an operation as its cause and either traps or sets their flags
+    /// and writes the result
+    fn complete(&mut self, output: Output, raised: FpuExceptions) -> Result<(), Cop1Error> {
+        if let Output::Double(reg, _) = output {
+            if reg & 1 != 0 {
+                return Err(Cop1Error::Reserved);
+            }
+        }
+        self.fcsr = self.fcsr & !(0b111111 << FCSR_CAUSE_SHIFT) | raised.bits() << FCSR_CAUSE_SHIFT;
+        if raised.intersects(self.trapping()) {
+            return Err(Cop1Error::Exception(raised));
+        }
+        self.fcsr |= (raised.bits() & 0b11111) << FCSR_FLAGS_SHIFT;
+        output.write(self)
+    }
+}
+
+/// Where the result of an instruction goes
+enum Output {
+    Single(usize, f32),
+    Double(usize, f64),
+    Word(usize, u32),
+    Condition(u32, bool),
+}
+
+impl Output {
+    fn write(self, fpu: &mut FpuState) -> Result<(), Cop1Error> {
+        match self {
+            Output::Single(reg, value) => fpu.set_single(reg, value),
+            Output::Double(reg, _) if reg & 1 != 0 => return Err(Cop1Error::Reserved),
+            Output::Double(reg, value) => fpu.set_double(reg, value),
+            Output::Word(reg, value) => fpu.fpr[reg] = value,
+            Output::Condition(cc, value) => fpu.set_condition(cc, value),
+        }
+        Ok(())
+    }
+}
+
+/// What the operations need from singles and doubles
+trait Float:
+    Copy
+    + PartialOrd
+    + Add<Output = Self>
+    + Sub<Output = Self>
+    + Mul<Output = Self>
+    + Div<Output = Self>
+    + Neg<Output = Self>
+{
+    const ZERO: Self;
+    const MAX: Self;
+    const MIN_POSITIVE: Self;
+    const INFINITY: Self;
+    /// The quiet NaN invalid operations produce
+    const DEFAULT_NAN: Self;
+
+    fn read(fpu: &FpuState, reg: usize) -> Option<Self>;
+    fn output(self, reg: usize) -> Output;
+
+    fn is_nan(self) -> bool;
+    fn is_signaling(self) -> bool;
+    fn is_finite(self) -> bool;
+    fn is_infinite(self) -> bool;
+    fn is_sign_negative(self) -> bool;
+    fn abs(self) -> Self;
+    fn sqrt(self) -> Self;
+    fn mul_add(self, a: Self, b: Self) -> Self;
+    fn next_up(self) -> Self;
+    fn to_f64(self) -> f64;
+    fn to_single(self, rm: RoundingMode) -> Option<(f32, FpuExceptions)>;
+    fn to_double(self) -> Option<(f64, FpuExceptions)>;
+
+    fn next_down(self) -> Self {
+        -(-self).next_up()
+    }
+
+    fn sign(self) -> Ordering {
+        self.partial_cmp(&Self::ZERO).unwrap_or(Ordering::Equal)
+    }
+}
+
+macro_rules! impl_float {
+    ($float:ty, $signaling_bit:expr, $default_nan:expr, { $($format:item)* }) => {
+        impl Float for $float {
+            const ZERO: Self = 0.0;
+            const MAX: Self = <$float>::MAX;
+            const MIN_POSITIVE: Self = <$float>::MIN_POSITIVE;
+            const INFINITY: Self = <$float>::INFINITY;
+            const DEFAULT_NAN: Self = <$float>::from_bits($default_nan);
+
+            fn is_nan(self) -> bool {
+                <$float>::is_nan(self)
+            }
+
+            fn is_signaling(self) -> bool {
+                self.is_nan() && self.to_bits() & $signaling_bit != 0
+            }
+
+            fn is_finite(self) -> bool {
+                <$float>::is_finite(self)
+            }
+
+            fn is_infinite(self) -> bool {
+                <$float>::is_infinite(self)
+            }
+
+            fn is_sign_negative(self) -> bool {
+                <$float>::is_sign_negative(self)
+            }
+
+            fn abs(self) -> Self {
+                <$float>::abs(self)
+            }
+
+            fn sqrt(self) -> Self {
+                <$float>::sqrt(self)
+            }
+
+            fn mul_add(self, a: Self, b: Self) -> Self {
+                <$float>::mul_add(self, a, b)
+            }
+
+            fn next_up(self) -> Self {
+                if self.is_nan() || self == Self::INFINITY {
+                    self
+                } else if self == 0.0 {
+                    <$float>::from_bits(1)
+                } else if self > 0.0 {
+                    <$float>::from_bits(self.to_bits() + 1)
+                } else {
+                    <$float>::from_bits(self.to_bits() - 1)
+                }
+            }
+
+            fn to_f64(self) -> f64 {
+                self as f64
+            }
+
+            $($format)*
+        }
+    };
+}
+
+impl_float!(f32, 1 << 22, 0x7FBFFFFF, {
+    fn read(fpu: &FpuState, reg: usize) -> Option<Self> {
+        Some(fpu.single(reg))
+    }
+
+    fn output(self, reg: usize) -> Output {
+        Output::Single(reg, self)
+    }
+
+    fn to_single(self, _: RoundingMode) -> Option<(f32, FpuExceptions)> {
+        None
+    }
+
+    fn to_double(self) -> Option<(f64, FpuExceptions)> {
+        Some(match nan_operands(self, self) {
+            Some((_, raised)) => (f64::DEFAULT_NAN, raised),
+            None => (self as f64, FpuExceptions::NONE),
+        })
+    }
+});
+
+impl_float!(f64, 1 << 51, 0x7FF7FFFFFFFFFFFF, {
+    fn read(fpu: &FpuState, reg: usize) -> Option<Self> {
+        fpu.double(reg)
+    }
+
+    fn output(self, reg: usize) -> Output {
+        Output::Double(reg, self)
+    }
+
+    fn to_single(self, rm: RoundingMode) -> Option<(f32, FpuExceptions)> {
+        if let Some((_, raised)) = nan_operands(self, self) {
+            return Some((f32::DEFAULT_NAN, raised));
+        }
+        let value = self as f32;
+        if value.is_infinite() && self.is_finite() {
+            return Some(overflow(rm, self.is_sign_negative()));
+        }
+        Some(finish(rm, value, self.total_cmp(&(value as f64))))
+    }
+
+    fn to_double(self) -> Option<(f64, FpuExceptions)> {
+        None
+    }
+});
+
+/// The default NaN if either operand is a NaN, invalid if one of them is signaling
+fn nan_operands<F: Float>(a: F, b: F) -> Option<(F, FpuExceptions)> {
+    if !a.is_nan() && !b.is_nan() {
+        return None;
+    }
+    let raised = if a.is_signaling() || b.is_signaling() {
+        FpuExceptions::INVALID
+    } else {
+        FpuExceptions::NONE
+    };
+    Some((F::DEFAULT_NAN, raised))
+}
+
+/// The result of an operation whose exact result overflowed
+fn overflow<F: Float>(rm: RoundingMode, negative: bool) -> (F, FpuExceptions) {
+    let value = match (rm, negative) {
+        (RoundingMode::Nearest, _)
+        | (RoundingMode::TowardPositive, false)
+        | (RoundingMode::TowardNegative, true) => F::INFINITY,
+        _ => F::MAX,
+    };
+    let value = if negative { -value } else { value };
+    (value, FpuExceptions::OVERFLOW | FpuExceptions::INEXACT)
+}
+
+/// Rounds the finite result of an operation on finite operands. `nearest` is the result rounded
+/// to nearest and `error` the sign of the exact result minus `nearest`
+fn finish<F: Float>(rm: RoundingMode, nearest: F, error: Ordering) -> (F, FpuExceptions) {
+    if error == Ordering::Equal {
+        return (nearest, FpuExceptions::NONE);
+    }
+    let value = match (rm, error) {
+        (RoundingMode::TowardPositive, Ordering::Greater) => nearest.next_up(),
+        (RoundingMode::TowardNegative, Ordering::Less) => nearest.next_down(),
+        (RoundingMode::TowardZero, Ordering::Greater) if nearest < F::ZERO => nearest.next_up(),
+        (RoundingMode::TowardZero, Ordering::Less) if nearest > F::ZERO => nearest.next_down(),
+        _ => nearest,
+    };
+    let mut raised = FpuExceptions::INEXACT;
+    if value.is_infinite() {
+        raised = raised | FpuExceptions::OVERFLOW;
+    }
+    if nearest.abs() < F::MIN_POSITIVE {
+        raised = raised | FpuExceptions::UNDERFLOW;
+    }
+    (value, raised)
+}
+
+fn add<F: Float>(rm: RoundingMode, a: F, b: F) -> (F, FpuExceptions) {
+    if let Some(nan) = nan_operands(a, b) {
+        return nan;
+    }
+    let sum = a + b;
+    if sum.is_nan() {
+        // infinities of opposite sign
+        return (F::DEFAULT_NAN, FpuExceptions::INVALID);
+    }
+    if !a.is_finite() || !b.is_finite() {
+        return (sum, FpuExceptions::NONE);
+    }
+    if sum.is_infinite() {
+        return overflow(rm, sum.is_sign_negative());
+    }
+    // the rounding error of the sum is exactly representable
+    let b_part = sum - a;
+    let error = (a - (sum - b_part)) + (b - b_part);
+    if sum.sign() == Ordering::Equal
+        && error.sign() == Ordering::Equal
+        && rm == RoundingMode::TowardNegative
+        && a.is_sign_negative() != b.is_sign_negative()
+    {
+        // an exact zero sum of operands with opposite signs is only negative when rounding down
+        return (-F::ZERO, FpuExceptions::NONE);
+    }
+    finish(rm, sum, error.sign())
+}
+
+fn mul<F: Float>(rm: RoundingMode, a: F, b: F) -> (F, FpuExceptions) {
+    if let Some(nan) = nan_operands(a, b) {
+        return nan;
+    }
+    let product = a * b;
+    if product.is_nan() {
+        // zero times infinity
+        return (F::DEFAULT_NAN, FpuExceptions::INVALID);
+    }
+    if !a.is_finite() || !b.is_finite() {
+        return (product, FpuExceptions::NONE);
+    }
+    if product.is_infinite() {
+        return overflow(rm, product.is_sign_negative());
+    }
+    finish(rm, product, a.mul_add(b, -product).sign())
+}
+
+fn div<F: Float>(rm: RoundingMode, a: F, b: F) -> (F, FpuExceptions) {
+    if let Some(nan) = nan_operands(a, b) {
+        return nan;
+    }
+    let quotient = a / b;
+    if quotient.is_nan() {
+        // zero by zero or infinity by infinity
+        return (F::DEFAULT_NAN, FpuExceptions::INVALID);
+    }
+    if b.sign() == Ordering::Equal && a.is_finite() {
+        return (quotient, FpuExceptions::DIVIDE_BY_ZERO);
+    }
+    if !a.is_finite() || !b.is_finite() {
+        return (quotient, FpuExceptions::NONE);
+    }
+    if quotient.is_infinite() {
+        return overflow(rm, quotient.is_sign_negative());
+    }
+    // a = quotient * b + remainder, so the error has the sign of remainder / b
+    let remainder = (-quotient).mul_add(b, a);
+    let error = if b < F::ZERO {
+        remainder.sign().reverse()
+    } else {
+        remainder.sign()
+    };
+    finish(rm, quotient, error)
+}
+
+fn sqrt<F: Float>(rm: RoundingMode, a: F) -> (F, FpuExceptions) {
+    if let Some(nan) = nan_operands(a, a) {
+        return nan;
+    }
+    if a < F::ZERO {
+        return (F::DEFAULT_NAN, FpuExceptions::INVALID);
+    }
+    let root = a.sqrt();
+    if !a.is_finite() {
+        return (root, FpuExceptions::NONE);
+    }
+    finish(rm, root, (-root).mul_add(root, a).sign())
+}
+
+/// ABS and NEG only change the sign but still treat signaling NaNs as invalid
+fn sign_op<F: Float>(a: F, value: F) -> (F, FpuExceptions) {
+    match nan_operands(a, a) {
+        Some(nan) => nan,
+        None => (value, FpuExceptions::NONE),
+    }
+}
+
+fn to_word<F: Float>(rm: RoundingMode, a: F, fd: usize) -> (Output, FpuExceptions) {
+    let value = a.to_f64();
+    let rounded = match rm {
+        RoundingMode::Nearest => value.round_ties_even(),
+        RoundingMode::TowardZero => value.trunc(),
+        RoundingMode::TowardPositive => value.ceil(),
+        RoundingMode::TowardNegative => value.floor(),
+    };
+    if value.is_nan() || rounded < i32::MIN as f64 || rounded > i32::MAX as f64 {
+        return (Output::Word(fd, INVALID_WORD), FpuExceptions::INVALID);
+    }
+    let raised = if rounded != value {
+        FpuExceptions::INEXACT
+    } else {
+        FpuExceptions::NONE
+    };
+    (Output::Word(fd, rounded as i32 as u32), raised)
+}
+
+/// C.cond, the lowest bits of `cond` select which of unordered, equal and less than make the
+/// condition true, the fourth bit makes unordered operands invalid
+fn compare<F: Float>(cond: u32, a: F, b: F) -> (bool, FpuExceptions) {
+    let unordered = a.is_nan() || b.is_nan();
+    let raised = if unordered && (cond & 0b1000 != 0 || a.is_signaling() || b.is_signaling()) {
+        FpuExceptions::INVALID
+    } else {
+        FpuExceptions::NONE
+    };
+    let value = (cond & 0b100 != 0 && a < b)
+        || (cond & 0b010 != 0 && a == b)
+        || (cond & 0b001 != 0 && unordered);
+    (value, raised)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const S: u32 = 0b10000;
+    const D: u32 = 0b10001;
+    const W: u32 = 0b10100;
+
+    const ADD: u32 = 0b000000;
+    const SUB: u32 = 0b000001;
+    const MUL: u32 = 0b000010;
+    const DIV: u32 = 0b000011;
+    const SQRT: u32 = 0b000100;
+    const ROUND_W: u32 = 0b001100;
+    const CEIL_W: u32 = 0b001110;
+    const FLOOR_W: u32 = 0b001111;
+    const CVT_S: u32 = 0b100000;
+    const CVT_W: u32 = 0b100100;
+
+    /// fd = fs op ft
+    fn op(fmt: u32, funct: u32, fd: u32, fs: u32, ft: u32) -> u32 {
+        0b010001 << 26 | fmt << 21 | ft << 16 | fs << 11 | fd << 6 | funct
+    }
+
+    fn cause(fpu: &FpuState) -> FpuExceptions {
+        FpuExceptions::from_bits(fpu.fcsr >> FCSR_CAUSE_SHIFT)
+    }
+
+    fn flags(fpu: &FpuState) -> FpuExceptions {
+        FpuExceptions::from_bits(fpu.fcsr >> FCSR_FLAGS_SHIFT & 0b11111)
+    }
+
+    /// Runs `funct` on the singles `a` and `b` under rounding mode `rm` (the FCSR RM field)
+    fn single(rm: u32, funct: u32, a: f32, b: f32) -> (f32, FpuExceptions) {
+        let mut fpu = FpuState {
+            fcsr: rm,
+            ..Default::default()
+        };
+        fpu.set_single(2, a);
+        fpu.set_single(4, b);
+        fpu.execute(op(S, funct, 0, 2, 4), &mut [0; 32]).unwrap();
+        // nothing traps, so every cause also sets its flag
+        assert_eq!(flags(&fpu), cause(&fpu));
+        (fpu.single(0), cause(&fpu))
+    }
+
+    #[test]
+    fn inexact_results_follow_the_rounding_mode() {
+        let half_ulp = f32::EPSILON / 2.0;
+        let up = 1.0f32.next_up();
+        let inexact = FpuExceptions::INEXACT;
+        assert_eq!(single(0, ADD, 1.0, half_ulp), (1.0, inexact));
+        assert_eq!(single(1, ADD, 1.0, half_ulp), (1.0, inexact));
+        assert_eq!(single(2, ADD, 1.0, half_ulp), (up, inexact));
+        assert_eq!(single(3, ADD, 1.0, half_ulp), (1.0, inexact));
+        assert_eq!(single(1, ADD, -1.0, -half_ulp), (-1.0, inexact));
+        assert_eq!(single(3, ADD, -1.0, -half_ulp), (-up, inexact));
+
+        // a third rounds up to nearest
+        let third = 1.0f32 / 3.0;
+        assert_eq!(single(2, DIV, 1.0, 3.0), (third, inexact));
+        assert_eq!(single(1, DIV, 1.0, 3.0), (third.next_down(), inexact));
+        assert_eq!(single(1, DIV, -1.0, 3.0), ((-third).next_up(), inexact));
+        assert_eq!(single(3, DIV, -1.0, 3.0), (-third, inexact));
+        assert_eq!(
+            single(2, SQRT, 2.0, 0.0),
+            (2.0f32.sqrt().next_up(), inexact)
+        );
+        assert_eq!(single(0, MUL, 3.0, 0.5), (1.5, FpuExceptions::NONE));
+    }
+
+    #[test]
+    fn exact_zero_differences_are_negative_only_when_rounding_down() {
+        let (zero, raised) = single(0, SUB, 1.0, 1.0);
+        assert!(zero == 0.0 && !zero.is_sign_negative() && raised.is_empty());
+        let (zero, raised) = single(3, SUB, 1.0, 1.0);
+        assert!(zero == 0.0 && zero.is_sign_negative() && raised.is_empty());
+    }
+
+    #[test]
+    fn overflow_and_invalid_results() {
+        let overflow = FpuExceptions::OVERFLOW | FpuExceptions::INEXACT;
+        assert_eq!(single(0, MUL, f32::MAX, 2.0), (f32::INFINITY, overflow));
+        assert_eq!(single(1, MUL, f32::MAX, 2.0), (f32::MAX, overflow));
+        assert_eq!(single(2, MUL, -f32::MAX, 2.0), (-f32::MAX, overflow));
+        assert_eq!(
+            single(0, DIV, -1.0, 0.0),
+            (f32::NEG_INFINITY, FpuExceptions::DIVIDE_BY_ZERO)
+        );
+
+        let (nan, raised) = single(0, SQRT, -1.0, 0.0);
+        assert_eq!(nan.to_bits(), f32::DEFAULT_NAN.to_bits());
+        assert_eq!(raised, FpuExceptions::INVALID);
+        let (nan, raised) = single(0, SUB, f32::INFINITY, f32::INFINITY);
+        assert_eq!(
+            (nan.to_bits(), raised),
+            (f32::DEFAULT_NAN.to_bits(), FpuExceptions::INVALID)
+        );
+        // quiet NaNs pass through without raising anything
+        let (nan, raised) = single(0, ADD, f32::DEFAULT_NAN, 1.0);
+        assert!(nan.is_nan() && raised.is_empty());
+    }
+
+    #[test]
+    fn conversions_to_words() {
+        let convert = |rm: u32, funct: u32, value: f64| {
+            let mut fpu = FpuState {
+                fcsr: rm,
+                ..Default::default()
+            };
+            fpu.set_double(2, value);
+            fpu.execute(op(D, funct, 0, 2, 0), &mut [0; 32]).unwrap();
+            (fpu.fpr[0] as i32, cause(&fpu))
+        };
+        let inexact = FpuExceptions::INEXACT;
+        assert_eq!(convert(0, ROUND_W, 2.5), (2, inexact));
+        assert_eq!(convert(0, ROUND_W, 3.5), (4, inexact));
+        assert_eq!(convert(0, CEIL_W, -2.5), (-2, inexact));
+        assert_eq!(convert(0, FLOOR_W, -2.5), (-3, inexact));
+        assert_eq!(convert(1, CVT_W, -2.5), (-2, inexact));
+        assert_eq!(convert(0, CVT_W, -7.0), (-7, FpuExceptions::NONE));
+        assert_eq!(
+            convert(0, CVT_W, 3e9),
+            (INVALID_WORD as i32, FpuExceptions::INVALID)
+        );
+
+        // a word that needs more than 24 bits rounds when it becomes a single
+        let mut fpu = FpuState {
+            fcsr: 2,
+            ..Default::default()
+        };
+        fpu.fpr[2] = (1 << 24) + 1;
+        fpu.execute(op(W, CVT_S, 0, 2, 0), &mut [0; 32]).unwrap();
+        assert_eq!(fpu.single(0), ((1 << 24) + 2) as f32);
+        assert_eq!(cause(&fpu), inexact);
+    }
+
+    #[test]
+    fn cause_is_replaced_and_flags_are_sticky() {
+        let mut fpu = FpuState::default();
+        fpu.set_single(2, 1.0);
+        fpu.set_single(4, 3.0);
+        fpu.execute(op(S, DIV, 0, 2, 4), &mut [0; 32]).unwrap();
+        assert_eq!(cause(&fpu), FpuExceptions::INEXACT);
+        fpu.execute(op(S, ADD, 0, 2, 4), &mut [0; 32]).unwrap();
+        assert_eq!(cause(&fpu), FpuExceptions::NONE);
+        assert_eq!(flags(&fpu), FpuExceptions::INEXACT);
+        assert_eq!(fpu.single(0), 4.0);
+    }
+
+    #[test]
+    fn enabled_exceptions_trap_without_writing() {
+        let mut fpu = FpuState::default();
+        let divide_by_zero = FpuExceptions::DIVIDE_BY_ZERO;
+        fpu.fcsr = divide_by_zero.bits() << FCSR_ENABLES_SHIFT;
+        fpu.set_single(2, 1.0);
+        fpu.set_single(0, 5.0);
+        assert_eq!(
+            fpu.execute(op(S, DIV, 0, 2, 4), &mut [0; 32]),
+            Err(Cop1Error::Exception(divide_by_zero))
+        );
+        assert_eq!(fpu.single(0), 5.0);
+        assert_eq!(cause(&fpu), divide_by_zero);
+        assert!(flags(&fpu).is_empty());
+
+        // enabling an exception whose cause is already set traps right away
+        let mut fpu = FpuState::default();
+        fpu.write_control(26, divide_by_zero.bits() << FCSR_CAUSE_SHIFT)
+            .unwrap();
+        assert_eq!(
+            fpu.write_control(28, divide_by_zero.bits() << FCSR_ENABLES_SHIFT),
+            Err(Cop1Error::Exception(divide_by_zero))
+        );
+    }
+
+    #[test]
+    fn doubles_need_even_registers() {
+        let mut fpu = FpuState::default();
+        assert_eq!(
+            fpu.execute(op(D, ADD, 0, 3, 4), &mut [0; 32]),
+            Err(Cop1Error::Reserved)
+        );
+        assert_eq!(
+            fpu.execute(op(D, ADD, 1, 2, 4), &mut [0; 32]),
+            Err(Cop1Error::Reserved)
+        );
+        assert_eq!(fpu.double(1), None);
+    }
+}
@@ -0,0 +1,260 @@
+//! Safe access to a tasks memory for the host, mainly for system calls that take pointers
+
+use crate::util::{page_number, page_offset, Page, PAGE_OFFSET_MASK, PAGE_SIZE};
+
+use super::{
+    MemoryAccess, PageVAddressStart, Protection, TaskError, TaskMemory, VmInstructionAddress, VmPtr,
+};
+
+impl<'b> TaskMemory<'b> {
+    /// Makes `page` visible at `v_page` with `protection` for the rest of the tasks run
+    ///
+    /// # Safety
+    ///
+    /// `page` must stay alive for as long as it is mapped here, in practice it must come from the
+    /// page pool, which never drops a page, and be removed again with [`TaskMemory::unmap_page`]
+    /// or [`TaskMemory::unmap_all`] before another task runs
+    pub unsafe fn map_page(
+        &mut self,
+        page: &Page,
+        v_page: PageVAddressStart,
+        protection: Protection,
+    ) {
+        //extend the lifetime
+        self.mem[v_page as usize] = Some(std::mem::transmute::<&Page, &Page>(page));
+        self.protection[v_page as usize] = protection;
+        self.mapped.push(v_page);
+    }
+
+    pub fn unmap_page(&mut self, v_page: PageVAddressStart) {
+        self.mem[v_page as usize] = None;
+        self.protection[v_page as usize] = Protection::NONE;
+    }
+
+    /// Unmaps every page mapped since the last call
+    pub fn unmap_all(&mut self) {
+        for v_page in std::mem::take(&mut self.mapped) {
+            self.unmap_page(v_page);
+        }
+        self.mapped_generations = None;
+    }
+
+    /// The page mapped at `address` no matter its protection
+    pub fn page(&self, address: VmPtr) -> Option<&'b Page> {
+        self.mem[page_number(address) as usize]
+    }
+
+    #[inline(always)]
+    pub fn executable_page(&self, address: VmPtr) -> Option<&'b Page> {
+        match self.mem[page_number(address) as usize] {
+            Some(page) if self.protection[page_number(address) as usize].executable() => Some(page),
+            _ => None,
+        }
+    }
+
+    fn page_or_err(
+        &self,
+        address: VmPtr,
+        access: MemoryAccess,
+        pc: VmInstructionAddress,
+    ) -> Result<&'b Page, TaskError> {
+        let page = self
+            .page(address)
+            .ok_or(TaskError::MemoryDoesNotExistError(access, address, pc))?;
+        if !self.protection[page_number(address) as usize].allows(access) {
+            return Err(TaskError::ProtectionFault(access, address, pc));
+        }
+        Ok(page)
+    }
+
+    pub fn read_u8(&self, address: VmPtr, pc: VmInstructionAddress) -> Result<u8, TaskError> {
+        Ok(self
+            .page_or_err(address, MemoryAccess::Read, pc)?
+            .get_u8(page_offset(address)))
+    }
+
+    pub fn write_u8(
+        &self,
+        address: VmPtr,
+        val: u8,
+        pc: VmInstructionAddress,
+    ) -> Result<(), TaskError> {
+        let page = self.page_or_err(address, MemoryAccess::Write, pc)?;
+        page.set_u8(page_offset(address), val);
+        if let Some(written) = &self.written {
+            written.mark(page, address);
+        }
+        Ok(())
+    }
+
+    /// Fills `buf` from memory starting at `address`, the range may span multiple pages
+    pub fn read_bytes(
+        &self,
+        address: VmPtr,
+        buf: &mut [u8],
+        pc: VmInstructionAddress,
+    ) -> Result<(), TaskError> {
+        for (index, byte) in buf.iter_mut().enumerate() {
+            let address = Self::offset(address, index, MemoryAccess::Read, pc)?;
+            *byte = self.read_u8(address, pc)?;
+        }
+        Ok(())
+    }
+
+    /// Writes `data` to memory starting at `address`, the range may span multiple pages.
+    /// Nothing is written if any part of the range isn't mapped writable
+    pub fn write_bytes(
+        &self,
+        address: VmPtr,
+        data: &[u8],
+        pc: VmInstructionAddress,
+    ) -> Result<(), TaskError> {
+        self.check_range(address, data.len(), MemoryAccess::Write, pc)?;
+        for (index, byte) in data.iter().enumerate() {
+            self.write_u8(address.wrapping_add(index as u32), *byte, pc)?;
+        }
+        Ok(())
+    }
+
+    pub fn read_u32(&self, address: VmPtr, pc: VmInstructionAddress) -> Result<u32, TaskError> {
+        let mut bytes = [0; 4];
+        self.read_bytes(address, &mut bytes, pc)?;
+        Ok(u32::from_le_bytes(bytes))
+    }
+
+    pub fn write_u32(
+        &self,
+        address: VmPtr,
+        val: u32,
+        pc: VmInstructionAddress,
+    ) -> Result<(), TaskError> {
+        self.write_bytes(address, &val.to_le_bytes(), pc)
+    }
+
+    pub fn read_u64(&self, address: VmPtr, pc: VmInstructionAddress) -> Result<u64, TaskError> {
+        let mut bytes = [0; 8];
+        self.read_bytes(address, &mut bytes, pc)?;
+        Ok(u64::from_le_bytes(bytes))
+    }
+
+    pub fn write_u64(
+        &self,
+        address: VmPtr,
+        val: u64,
+        pc: VmInstructionAddress,
+    ) -> Result<(), TaskError> {
+        self.write_bytes(address, &val.to_le_bytes(), pc)
+    }
+
+    /// Reads bytes starting at `address` up to but not including the first \0
+    pub fn read_c_string(
+        &self,
+        address: VmPtr,
+        pc: VmInstructionAddress,
+    ) -> Result<Vec<u8>, TaskError> {
+        let mut str = Vec::new();
+        loop {
+            let byte = self.read_u8(
+                Self::offset(address, str.len(), MemoryAccess::Read, pc)?,
+                pc,
+            )?;
+            if byte == 0 {
+                return Ok(str);
+            }
+            str.push(byte);
+        }
+    }
+
+    /// Reads a buffer laid out as a u32 length followed by that many bytes
+    pub fn read_buffer(
+        &self,
+        address: VmPtr,
+        pc: VmInstructionAddress,
+    ) -> Result<Vec<u8>, TaskError> {
+        let len = self.read_u32(address, pc)? as usize;
+        let start = Self::offset(address, 4, MemoryAccess::Read, pc)?;
+        // the length comes from the guest, nothing is allocated before the range is known to exist
+        self.check_range(start, len, MemoryAccess::Read, pc)?;
+        let mut buf = vec![0; len];
+        self.read_bytes(start, &mut buf, pc)?;
+        Ok(buf)
+    }
+
+    /// Writes `data` as a u32 length followed by the bytes themselves
+    pub fn write_buffer(
+        &self,
+        address: VmPtr,
+        data: &[u8],
+        pc: VmInstructionAddress,
+    ) -> Result<(), TaskError> {
+        let start = Self::offset(address, 4, MemoryAccess::Write, pc)?;
+        // check the whole buffer before writing the length so a failed write leaves nothing behind
+        self.write_bytes(start, data, pc)?;
+        self.write_u32(address, data.len() as u32, pc)
+    }
+
+    /// Checks that the `len` bytes starting at `address` are all mapped to allow `access`, looking
+    /// at each page only once
+    fn check_range(
+        &self,
+        address: VmPtr,
+        len: usize,
+        access: MemoryAccess,
+        pc: VmInstructionAddress,
+    ) -> Result<(), TaskError> {
+        if len == 0 {
+            return Ok(());
+        }
+        let last = Self::offset(address, len - 1, access, pc)?;
+        let mut page = address;
+        while page_number(page) < page_number(last) {
+            self.page_or_err(page, access, pc)?;
+            page = (page & !PAGE_OFFSET_MASK) + PAGE_SIZE;
+        }
+        self.page_or_err(last, access, pc)?;
+        Ok(())
+    }
+
+    /// `address + offset` or an error if that would wrap around the end of the address space
+    fn offset(
+        address: VmPtr,
+        offset: usize,
+        access: MemoryAccess,
+        pc: VmInstructionAddress,
+    ) -> Result<VmPtr, TaskError> {
+        u32::try_from(offset)
+            .ok()
+            .and_then(|offset| address.checked_add(offset))
+            .ok_or(TaskError::MemoryDoesNotExistError(access, address, pc))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn buffers_past_mapped_memory_are_an_error() {
+        let page = Page::new();
+        let mut mem = TaskMemory::new();
+        unsafe { mem.map_page(&page, 1, Protection::READ_WRITE) };
+        let address = PAGE_SIZE + 8;
+
+        mem.write_buffer(address, &[1, 2, 3], 4).unwrap();
+        assert_eq!(mem.read_buffer(address, 4).unwrap(), [1, 2, 3]);
+
+        // a length no memory could hold fails on the first missing page
+        mem.write_u32(address, u32::MAX / 2, 4).unwrap();
+        assert!(matches!(
+            mem.read_buffer(address, 4),
+            Err(TaskError::MemoryDoesNotExistError(MemoryAccess::Read, a, 4)) if a == 2 * PAGE_SIZE
+        ));
+        // as does one that wraps around the address space
+        mem.write_u32(address, u32::MAX, 4).unwrap();
+        assert!(matches!(
+            mem.read_buffer(address, 4),
+            Err(TaskError::MemoryDoesNotExistError(MemoryAccess::Read, ..))
+        ));
+        mem.unmap_all();
+    }
+}
@@ -1,12 +1,27 @@
-use std::{fmt::Debug, sync::atomic::AtomicBool};
+pub mod cop0;
+pub mod fpu;
+mod memory;
+pub mod written;
+
+use std::{
+    collections::BTreeMap,
+    fmt::Debug,
+    sync::{
+        atomic::{AtomicU64, Ordering},
+        Mutex,
+    },
+};
 
+use cop0::{Cop0State, Fault};
+use fpu::{Cop1Error, FpuExceptions, FpuState};
 use rclite::Arc;
+pub use written::{UninitializedRead, WriteTracker};
 
 use crate::{
-    scheduler::{self, SchedulerTask},
-    system::System,
+    scheduler::{self, SchedulerTask, WaitKey},
+    system::{Limit, PageFault, System},
     taskpool::PageId,
-    util::{Page, ProcessId, TaskId},
+    util::{page_address, page_number, page_offset, Page, ProcessId, TaskId, PAGE_COUNT},
 };
 
 #[derive(Debug)]
@@ -16,6 +31,7 @@ pub struct Task {
     pub name: Option<String>,
     pub vm_state: VmState,
     pub memory_mapping: TaskMemoryMapping,
+    pub delay_slots: DelaySlots,
 }
 
 impl Task {
@@ -34,6 +50,7 @@ impl Task {
             vm_state: Default::default(),
             memory_mapping: Default::default(),
             name: None,
+            delay_slots: DelaySlots::default(),
         }
     }
 
@@ -42,11 +59,484 @@ impl Task {
     }
 }
 
-pub type PageVAddressStart = u16;
+pub type PageVAddressStart = u32;
+
+/// The first page above the highest stack, the main thread's stack pointer starts at 0x80000000
+pub const STACK_REGION_TOP: u32 = page_number(0x80000000);
+
+/// Stack size of the main thread of a process
+pub const MAIN_STACK_SIZE: u32 = 0x10000;
+
+/// Stack size of a thread that doesn't ask for a specific one
+pub const DEFAULT_STACK_SIZE: u32 = 0x4000;
+
+/// A range of pages reserved for a threads stack. The stack grows down from `top` and the page
+/// just below its last page is left unmapped as a guard
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct StackRange {
+    /// First page above the stack
+    pub top: u32,
+    pub pages: u32,
+}
+
+impl StackRange {
+    pub fn bottom(&self) -> PageVAddressStart {
+        self.top - self.pages
+    }
+
+    pub fn guard_page(&self) -> PageVAddressStart {
+        self.bottom() - 1
+    }
+
+    /// The initial stack pointer of a thread using this stack
+    pub fn stack_pointer(&self) -> VmPtr {
+        page_address(self.top)
+    }
+}
+
+/// What a task may do with a mapped page, the bits match the flags of ELF program headers
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub struct Protection(u8);
+
+impl Protection {
+    pub const NONE: Self = Self(0);
+    pub const EXECUTE: Self = Self(0b001);
+    pub const WRITE: Self = Self(0b010);
+    pub const READ: Self = Self(0b100);
+    pub const READ_WRITE: Self = Self(0b110);
+    pub const READ_EXECUTE: Self = Self(0b101);
+    pub const ALL: Self = Self(0b111);
+    /// The page is shared with another process and gets copied by the first write to it, until
+    /// then it isn't writable no matter what the other bits say
+    pub const COPY_ON_WRITE: Self = Self(0b1000);
+    /// The page belongs to shared memory that every process mapping it should see writes to, so
+    /// it stays shared when the process is cloned instead of becoming copy on write
+    pub const SHARED: Self = Self(0b10000);
+
+    /// Takes the lowest three bits of `bits`, anything else is ignored
+    pub const fn from_bits(bits: u32) -> Self {
+        Self(bits as u8 & 0b111)
+    }
+
+    pub const fn bits(self) -> u32 {
+        self.0 as u32
+    }
+
+    pub const fn union(self, other: Self) -> Self {
+        Self(self.0 | other.0)
+    }
+
+    #[inline(always)]
+    pub const fn readable(self) -> bool {
+        self.0 & Self::READ.0 != 0
+    }
+
+    #[inline(always)]
+    pub const fn writable(self) -> bool {
+        self.0 & (Self::WRITE.0 | Self::COPY_ON_WRITE.0) == Self::WRITE.0
+    }
+
+    pub const fn is_copy_on_write(self) -> bool {
+        self.0 & Self::COPY_ON_WRITE.0 != 0
+    }
+
+    pub const fn is_shared(self) -> bool {
+        self.0 & Self::SHARED.0 != 0
+    }
+
+    /// Marks a writable page as copy on write, other pages don't need it as they are never
+    /// written anyway and shared memory is meant to be written by both
+    pub const fn shared_copy_on_write(self) -> Self {
+        if self.0 & Self::WRITE.0 != 0 && !self.is_shared() {
+            Self(self.0 | Self::COPY_ON_WRITE.0)
+        } else {
+            self
+        }
+    }
+
+    pub const fn without_copy_on_write(self) -> Self {
+        Self(self.0 & !Self::COPY_ON_WRITE.0)
+    }
+
+    #[inline(always)]
+    pub const fn executable(self) -> bool {
+        self.0 & Self::EXECUTE.0 != 0
+    }
+
+    pub const fn allows(self, access: MemoryAccess) -> bool {
+        match access {
+            MemoryAccess::Read => self.readable(),
+            MemoryAccess::Write => self.writable(),
+            MemoryAccess::Execute => self.executable(),
+        }
+    }
+}
+
+impl std::fmt::Display for Protection {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        let flag = |set, c| if set { c } else { '-' };
+        let write = if self.is_copy_on_write() { 'c' } else { 'w' };
+        write!(
+            f,
+            "{}{}{}",
+            flag(self.readable(), 'r'),
+            flag(self.0 & Self::WRITE.0 != 0, write),
+            flag(self.executable(), 'x')
+        )
+    }
+}
+
+impl std::ops::BitOr for Protection {
+    type Output = Self;
+
+    fn bitor(self, rhs: Self) -> Self::Output {
+        self.union(rhs)
+    }
+}
+
+/// The kind of access that caused a memory error, [`MemoryAccess::Execute`] is fetching an
+/// instruction
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum MemoryAccess {
+    Read,
+    Write,
+    Execute,
+}
+
+/// A range of the address space that gets zeroed pages, or whatever the page fault handler of
+/// the system gives it, the first time each page is touched
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Reservation {
+    pub start: PageVAddressStart,
+    pub pages: u32,
+    pub protection: Protection,
+}
+
+impl Reservation {
+    pub fn end(&self) -> PageVAddressStart {
+        self.start + self.pages
+    }
+
+    pub fn contains(&self, v_page: PageVAddressStart) -> bool {
+        (self.start..self.end()).contains(&v_page)
+    }
+}
+
+/// Source of [`AddressSpace::generation`], shared by every address space so that no two states
+/// of any of them get the same number
+static GENERATION: AtomicU64 = AtomicU64::new(1);
+
+fn next_generation() -> u64 {
+    GENERATION.fetch_add(1, Ordering::Relaxed)
+}
+
+/// The pages shared by every thread of a process
+pub struct AddressSpace {
+    /// Every mapped page with its protection by virtual page number
+    mapping: BTreeMap<PageVAddressStart, (Arc<Page>, Protection)>,
+    /// Changes whenever a page is mapped, unmapped or changes its protection
+    generation: u64,
+    /// Ranges that only get pages once they are touched
+    pub reserved: Vec<Reservation>,
+    /// The lowest guard page of any stack allocated so far, stacks are allocated downwards
+    stack_floor: u32,
+    free_stacks: Vec<StackRange>,
+}
+
+impl Default for AddressSpace {
+    fn default() -> Self {
+        Self {
+            mapping: BTreeMap::new(),
+            generation: next_generation(),
+            reserved: Vec::new(),
+            stack_floor: STACK_REGION_TOP,
+            free_stacks: Vec::new(),
+        }
+    }
+}
+
+impl AddressSpace {
+    pub fn page(&self, v_page: PageVAddressStart) -> Option<&Arc<Page>> {
+        self.mapping.get(&v_page).map(|(page, _)| page)
+    }
+
+    pub fn protection(&self, v_page: PageVAddressStart) -> Option<Protection> {
+        self.mapping.get(&v_page).map(|(_, protection)| *protection)
+    }
+
+    /// Every mapped page with its protection, ordered by address
+    pub fn mappings(&self) -> impl Iterator<Item = (PageVAddressStart, &Arc<Page>, Protection)> {
+        self.mapping
+            .iter()
+            .map(|(v_page, (page, protection))| (*v_page, page, *protection))
+    }
+
+    /// A number that is different for every state of the mapped pages of every address space,
+    /// so memory set up for one state can be kept for as long as the number stays the same
+    pub fn generation(&self) -> u64 {
+        self.generation
+    }
+
+    fn changed(&mut self) {
+        self.generation = next_generation();
+    }
+
+    /// Maps `page` at `v_page`, replacing whatever was mapped there before
+    pub fn map(&mut self, page: Arc<Page>, v_page: PageVAddressStart, protection: Protection) {
+        self.mapping.insert(v_page, (page, protection));
+        self.changed();
+    }
 
+    /// Changes the protection of an already mapped page, returns false if nothing is mapped at
+    /// `v_page`
+    pub fn protect(&mut self, v_page: PageVAddressStart, protection: Protection) -> bool {
+        match self.mapping.get_mut(&v_page) {
+            Some(mapping) => {
+                mapping.1 = protection;
+                self.changed();
+                true
+            }
+            None => false,
+        }
+    }
+
+    pub fn unmap(&mut self, v_page: PageVAddressStart) -> Option<Arc<Page>> {
+        let (page, _) = self.mapping.remove(&v_page)?;
+        self.changed();
+        Some(page)
+    }
+
+    /// Unmaps every page, handing back the pages that were mapped
+    pub fn take_pages(&mut self) -> Vec<Arc<Page>> {
+        self.changed();
+        std::mem::take(&mut self.mapping)
+            .into_values()
+            .map(|(page, _)| page)
+            .collect()
+    }
+
+    /// Every range of pages that is mapped or reserved as `start..end`
+    fn used(&self) -> impl Iterator<Item = (u32, u32)> + '_ {
+        self.mapping
+            .keys()
+            .map(|v_page| (*v_page, *v_page + 1))
+            .chain(self.reserved.iter().map(|res| (res.start, res.end())))
+    }
+
+    /// Whether none of the `pages` pages starting at `v_page` are mapped or reserved in any way
+    pub fn is_free(&self, v_page: PageVAddressStart, pages: u32) -> bool {
+        let end = v_page as u64 + pages as u64;
+        end <= self.stack_floor as u64
+            && !self
+                .used()
+                .any(|(start, used_end)| (start as u64) < end && v_page < used_end)
+    }
+
+    /// Finds the lowest run of `pages` free pages below the stack region, page 0 is never handed
+    /// out so that a null pointer always stays invalid
+    pub fn find_free(&self, pages: u32) -> Option<PageVAddressStart> {
+        if pages == 0 {
+            return None;
+        }
+        let mut used: Vec<(u32, u32)> = self.used().collect();
+        used.sort_unstable();
+
+        let mut start = 1;
+        for (used_start, used_end) in used {
+            if used_start >= start + pages {
+                break;
+            }
+            start = start.max(used_end);
+        }
+        (start + pages <= self.stack_floor).then_some(start)
+    }
+
+    /// Reserves `pages` pages starting at `start` to be backed on first touch, fails if any of
+    /// them is already in use
+    pub fn reserve(
+        &mut self,
+        start: PageVAddressStart,
+        pages: u32,
+        protection: Protection,
+    ) -> bool {
+        if pages == 0 || !self.is_free(start, pages) {
+            return false;
+        }
+        self.reserved.push(Reservation {
+            start,
+            pages,
+            protection,
+        });
+        true
+    }
+
+    /// The protection a page gets when the reservation covering `v_page` is touched
+    pub fn reservation(&self, v_page: PageVAddressStart) -> Option<Protection> {
+        self.reserved
+            .iter()
+            .find(|res| res.contains(v_page))
+            .map(|res| res.protection)
+    }
+
+    /// Drops `start..end` from every reservation, splitting reservations where needed
+    pub fn unreserve(&mut self, start: PageVAddressStart, end: PageVAddressStart) {
+        if start >= end {
+            return;
+        }
+        let mut kept = Vec::with_capacity(self.reserved.len());
+        for res in self.reserved.drain(..) {
+            if res.start < start {
+                kept.push(Reservation {
+                    pages: res.pages.min(start - res.start),
+                    ..res
+                });
+            }
+            if res.end() > end {
+                let new_start = res.start.max(end);
+                kept.push(Reservation {
+                    start: new_start,
+                    pages: res.end() - new_start,
+                    ..res
+                });
+            }
+        }
+        self.reserved = kept;
+    }
+
+    /// Reserves a range for a new stack of `pages` pages below every other stack, or reuses the
+    /// range of a thread that has exited
+    pub fn allocate_stack(&mut self, pages: u32) -> Option<StackRange> {
+        if let Some(index) = self.free_stacks.iter().position(|s| s.pages == pages) {
+            return Some(self.free_stacks.swap_remove(index));
+        }
+        self.allocate_stack_at(self.stack_floor, pages)
+    }
+
+    /// Reserves the stack range right below the page `top`, fails if any part of it or its guard
+    /// page is already mapped
+    pub fn allocate_stack_at(&mut self, top: u32, pages: u32) -> Option<StackRange> {
+        // the stack pointer is the address at the top of the stack so it has to fit in a u32
+        if pages == 0 || top as usize >= PAGE_COUNT || top <= pages {
+            return None;
+        }
+        let stack = StackRange { top, pages };
+        if self
+            .used()
+            .any(|(start, end)| start < top && stack.guard_page() < end)
+        {
+            return None;
+        }
+        self.stack_floor = self.stack_floor.min(stack.guard_page());
+        Some(stack)
+    }
+
+    pub fn release_stack(&mut self, stack: StackRange) {
+        self.free_stacks.push(stack);
+    }
+
+    /// Whether `v_page` lies inside a range reserved for stacks
+    pub fn in_stack_region(&self, v_page: PageVAddressStart) -> bool {
+        (self.stack_floor..STACK_REGION_TOP).contains(&v_page)
+    }
+}
+
+/// What a task can see of memory, the address space of its process plus the pages private to the
+/// task like its stack. Private pages take priority over shared ones at the same address
 #[derive(Default)]
 pub struct TaskMemoryMapping {
-    pub mapping: Vec<(Arc<Page>, PageVAddressStart)>,
+    pub address_space: Arc<Mutex<AddressSpace>>,
+    pub private: AddressSpace,
+    /// The range of this tasks stack, given back to the address space when the task is dropped
+    pub stack: Option<StackRange>,
+}
+
+impl TaskMemoryMapping {
+    /// A mapping for a new thread of the same process, sharing everything but the private pages
+    pub fn new_thread(&self) -> Self {
+        Self {
+            address_space: self.address_space.clone(),
+            private: AddressSpace::default(),
+            stack: None,
+        }
+    }
+
+    /// Maps fresh private pages for `stack` and makes it this tasks stack
+    pub fn map_stack(&mut self, stack: StackRange, mut new_page: impl FnMut() -> Arc<Page>) {
+        for v_page in stack.bottom()..stack.top {
+            self.private.map(new_page(), v_page, Protection::READ_WRITE);
+        }
+        self.stack = Some(stack);
+    }
+
+    /// A mapping for a new process that starts out with the same memory as this task. Every
+    /// writable page, including this tasks stack, becomes copy on write in both mappings. Stacks
+    /// of other threads are not part of the new mapping
+    pub fn fork(&mut self) -> Self {
+        let mut shared = self.address_space.lock().unwrap();
+        let mut forked = AddressSpace {
+            reserved: shared.reserved.clone(),
+            ..Default::default()
+        };
+        for (v_page, (page, protection)) in &mut shared.mapping {
+            *protection = protection.shared_copy_on_write();
+            forked.mapping.insert(*v_page, (page.clone(), *protection));
+        }
+        shared.changed();
+        let stack = self
+            .stack
+            .and_then(|stack| forked.allocate_stack_at(stack.top, stack.pages));
+        drop(shared);
+
+        let mut private = AddressSpace::default();
+        for (v_page, (page, protection)) in &mut self.private.mapping {
+            *protection = protection.shared_copy_on_write();
+            private.mapping.insert(*v_page, (page.clone(), *protection));
+        }
+        self.private.changed();
+
+        Self {
+            address_space: Arc::new(Mutex::new(forked)),
+            private,
+            stack,
+        }
+    }
+
+    /// Every mapped page with its protection, private pages last so they win over shared pages
+    /// at the same address
+    pub fn for_each_mapping(&self, mut f: impl FnMut(&Arc<Page>, PageVAddressStart, Protection)) {
+        let shared = self.address_space.lock().unwrap();
+        for (v_page, page, protection) in shared.mappings().chain(self.private.mappings()) {
+            f(page, v_page, protection);
+        }
+    }
+
+    pub fn is_stack_guard(&self, address: VmPtr) -> bool {
+        self.stack
+            .is_some_and(|stack| stack.guard_page() == page_number(address))
+    }
+
+    pub fn page(&self, v_page: PageVAddressStart) -> Option<Arc<Page>> {
+        if let Some(page) = self.private.page(v_page) {
+            return Some(page.clone());
+        }
+        self.address_space.lock().unwrap().page(v_page).cloned()
+    }
+
+    /// The page visible at `v_page` together with its protection
+    pub fn mapping(&self, v_page: PageVAddressStart) -> Option<(Arc<Page>, Protection)> {
+        let find = |space: &AddressSpace| space.mapping.get(&v_page).cloned();
+        find(&self.private).or_else(|| find(&self.address_space.lock().unwrap()))
+    }
+}
+
+impl Drop for TaskMemoryMapping {
+    fn drop(&mut self) {
+        if let Some(stack) = self.stack.take() {
+            if let Ok(mut shared) = self.address_space.lock() {
+                shared.release_stack(stack);
+            }
+        }
+    }
 }
 
 impl Debug for TaskMemoryMapping {
@@ -54,32 +544,73 @@ impl Debug for TaskMemoryMapping {
         struct Mapped {
             p_id: usize,
             pvas: PageVAddressStart,
+            protection: Protection,
         }
         impl Debug for Mapped {
             fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
                 f.debug_struct(&format!(
-                    "PageId: {} -> VAddress: {:#010X}",
+                    "PageId: {} -> VAddress: {:#010X} {}",
                     self.p_id,
-                    (self.pvas as u32) << 16
+                    page_address(self.pvas),
+                    self.protection
                 ))
                 .finish()
             }
         }
-        f.debug_list()
-            .entries(self.mapping.iter().map(|(p_id, pvas)| Mapped {
-                p_id: 0xFF,
-                pvas: *pvas,
-            }))
+        let shared = self.address_space.lock().unwrap();
+        f.debug_struct("TaskMemoryMapping")
+            .field(
+                "shared",
+                &shared
+                    .mappings()
+                    .map(|(pvas, _, protection)| Mapped {
+                        p_id: 0xFF,
+                        pvas,
+                        protection,
+                    })
+                    .collect::<Vec<_>>(),
+            )
+            .field(
+                "private",
+                &self
+                    .private
+                    .mappings()
+                    .map(|(pvas, _, protection)| Mapped {
+                        p_id: 0xFF,
+                        pvas,
+                        protection,
+                    })
+                    .collect::<Vec<_>>(),
+            )
             .finish()
     }
 }
 
-#[derive(Default)]
+/// How the instruction after a branch or jump, its delay slot, is run
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub enum DelaySlots {
+    /// Delay slots are never run, a taken branch jumps right away and one that isn't taken skips
+    /// the slot. This is what code built with `--disable-mips-delay-filler` expects since it only
+    /// puts nops into delay slots
+    #[default]
+    Skipped,
+    /// The delay slot runs before control is transferred like it does on hardware, for a branch
+    /// likely only if the branch is taken. A branch inside a delay slot takes effect after the
+    /// first instruction at the target of the outer branch
+    Executed,
+}
+
+#[derive(Default, Clone)]
 pub struct VmState {
     pub pc: u32,
     pub hi: u32,
     pub lo: u32,
     pub reg: [u32; 32],
+    /// Target of a taken branch whose delay slot runs next, only used with
+    /// [`DelaySlots::Executed`]
+    pub delayed_branch: Option<u32>,
+    pub fpu: FpuState,
+    pub cop0: Cop0State,
 }
 
 impl Debug for VmState {
@@ -88,6 +619,7 @@ impl Debug for VmState {
             .field("pc", &self.pc)
             .field("hi", &self.hi)
             .field("lo", &self.lo)
+            .field("delayed_branch", &self.delayed_branch)
             .field("$zero", &self.reg[0])
             .field("$1/at", &self.reg[1])
             .field("$2/v0", &self.reg[2])
@@ -120,6 +652,8 @@ impl Debug for VmState {
             .field("$29/sp", &self.reg[29])
             .field("$30/s8/fp", &self.reg[30])
             .field("$31/ra", &self.reg[31])
+            .field("fpu", &self.fpu)
+            .field("cop0", &self.cop0)
             .finish()
     }
 }
@@ -224,7 +758,9 @@ fn unlikely(b: bool) -> bool {
 pub enum TaskRunResult {
     Continue,
     Wait(u32),
+    Block(u32, WaitKey),
     Exit(u32, u32),
+    ExitProcess(u32, u32),
 }
 
 pub type VmPtr = u32;
@@ -234,89 +770,306 @@ pub type VmInstructionAddress = VmPtr;
 #[derive(Debug)]
 pub enum TaskError {
     DivByZeroError(VmInstructionAddress),
-    MemoryDoesNotExistError(VmPtr, VmInstructionAddress),
+    MemoryDoesNotExistError(MemoryAccess, VmPtr, VmInstructionAddress),
     InvalidOperation(VmInstructionAddress, VmInstruction),
-    MemoryAllignmentError(u8, VmInstructionAddress),
+    MemoryAllignmentError(u8, MemoryAccess, VmPtr, VmInstructionAddress),
     OverflowError(VmInstructionAddress),
+    /// An access hit the guard page below the tasks stack
+    StackOverflow(MemoryAccess, VmPtr, VmInstructionAddress),
+    /// The page at the address is mapped but doesn't allow the access
+    ProtectionFault(MemoryAccess, VmPtr, VmInstructionAddress),
+    /// The process needed more of something than its limits allow
+    LimitExceeded(Limit, VmInstructionAddress),
+    /// A conditional trap instruction trapped, with the code field of the instruction, which is
+    /// always 0 for the immediate forms
+    Trap(u32, VmInstructionAddress),
+    /// A floating point operation raised exceptions that are enabled in the FCSR
+    FloatingPointException(FpuExceptions, VmInstructionAddress),
 }
 
-pub struct TaskMemory<'a, 'b> {
-    pub ll_bit: &'a AtomicBool,
-    pub mem: [Option<&'b Page>; 0x10000],
+pub struct TaskMemory<'b> {
+    /// The word reserved by the last LL of the running task as given by [`word_identity`], 0 if
+    /// there is none. A store to the word or a context switch drops the reservation
+    pub ll_reservation: usize,
+    /// Indexed by page number, boxed as the tables get large with small pages
+    pub mem: Box<[Option<&'b Page>; PAGE_COUNT]>,
+    pub protection: Box<[Protection; PAGE_COUNT]>,
+    /// Set when reads of never written memory should be reported
+    pub written: Option<Arc<WriteTracker>>,
+    /// Every page mapped since the tables were last cleared
+    mapped: Vec<PageVAddressStart>,
+    /// The [`AddressSpace::generation`] of the shared and private pages the tables were set up
+    /// for, the tables can be reused as they are as long as neither changes
+    pub mapped_generations: Option<(u64, u64)>,
 }
 
-impl<'a, 'b> TaskMemory<'a, 'b> {
-    pub fn new(ll_bit: &'a AtomicBool) -> Self {
+impl<'b> TaskMemory<'b> {
+    pub fn new() -> Self {
         TaskMemory {
-            ll_bit,
-            mem: [None; 0x10000],
+            ll_reservation: 0,
+            mem: vec![None; PAGE_COUNT]
+                .into_boxed_slice()
+                .try_into()
+                .unwrap(),
+            protection: vec![Protection::NONE; PAGE_COUNT]
+                .into_boxed_slice()
+                .try_into()
+                .unwrap(),
+            written: None,
+            mapped: Vec::new(),
+            mapped_generations: None,
         }
     }
 }
 
+impl Default for TaskMemory<'_> {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+/// The host address of the word containing `address` in `page`. Pages never move so this tells
+/// words apart no matter which process maps them or at which address
+#[inline(always)]
+pub fn word_identity(page: &Page, address: VmPtr) -> usize {
+    page as *const Page as usize + (page_offset(address) & !0b11) as usize
+}
+
 impl Task {
+    /// Gives this process its own copy of the copy on write page at `address` and maps it into
+    /// `mem`, returns `None` if the page isn't copy on write. Host code that wants to write into
+    /// guest memory has to do this first for pages that are shared copy on write
+    #[cold]
+    pub fn copy_on_write<'b>(
+        &mut self,
+        sys: &System,
+        mem: &mut TaskMemory<'b>,
+        address: VmPtr,
+    ) -> Option<&'b Page> {
+        let v_page = page_number(address);
+        let pid = self.pid;
+        let resolve = |space: &mut AddressSpace| {
+            let (page, protection) = space.mapping.get_mut(&v_page)?;
+            if !protection.is_copy_on_write() {
+                return None;
+            }
+            *protection = protection.without_copy_on_write();
+            // one reference is held by the page pool and one by this mapping, anything more is
+            // another process still sharing it
+            if page.strong_count() > 2 {
+                *page = sys.page_pool().copy_page(page, pid);
+            }
+            let resolved = (page.clone(), *protection);
+            space.changed();
+            Some(resolved)
+        };
+
+        let (page, protection) = if self.memory_mapping.private.page(v_page).is_some() {
+            resolve(&mut self.memory_mapping.private)?
+        } else {
+            resolve(&mut self.memory_mapping.address_space.lock().unwrap())?
+        };
+        // the mapping holds on to the page for the rest of the run
+        unsafe { mem.map_page(&page, v_page, protection) };
+        mem.page(address)
+    }
+
+    /// Resolves an access to `address` that `mem` doesn't allow as is. Unmapped pages are handed
+    /// to the page fault handling of the system and copy on write pages are copied, after which
+    /// the page is mapped into `mem`. Host code should use this before giving up on an address
+    /// so that pages which were never touched still work
+    #[cold]
+    pub fn fault_in<'b>(
+        &mut self,
+        sys: &mut System,
+        mem: &mut TaskMemory<'b>,
+        address: VmPtr,
+        access: MemoryAccess,
+    ) -> Result<&'b Page, TaskError> {
+        let v_page = page_number(address);
+        if mem.page(address).is_none() {
+            let fault = PageFault {
+                address,
+                access,
+                pc: self.vm_state.pc,
+            };
+            if sys.handle_page_fault(self, fault)? {
+                if let Some((page, protection)) = self.memory_mapping.mapping(v_page) {
+                    // the mapping holds on to the page for the rest of the run
+                    unsafe { mem.map_page(&page, v_page, protection) };
+                }
+            }
+        }
+        if access == MemoryAccess::Write {
+            // the page might have to be copied which takes another page
+            if mem.protection[v_page as usize].is_copy_on_write()
+                && !sys.within_limit(self.pid, Limit::Pages, 1)
+            {
+                return Err(TaskError::LimitExceeded(Limit::Pages, self.vm_state.pc));
+            }
+            let shared = mem.page(address);
+            if let Some(page) = self.copy_on_write(sys, mem, address) {
+                if let Some(shared) = shared.filter(|shared| !std::ptr::eq(*shared, page)) {
+                    sys.page_copied(self.pid, shared, page);
+                }
+                return Ok(page);
+            }
+        }
+        match mem.page(address) {
+            Some(page) if mem.protection[v_page as usize].allows(access) => Ok(page),
+            _ => Err(self.memory_error(mem, address, access)),
+        }
+    }
+
+    #[cold]
+    fn memory_error(&self, mem: &TaskMemory, address: VmPtr, access: MemoryAccess) -> TaskError {
+        if mem.page(address).is_some() {
+            TaskError::ProtectionFault(access, address, self.vm_state.pc)
+        } else if self.memory_mapping.is_stack_guard(address) {
+            TaskError::StackOverflow(access, address, self.vm_state.pc)
+        } else {
+            TaskError::MemoryDoesNotExistError(access, address, self.vm_state.pc)
+        }
+    }
+
     pub fn run(
         &mut self,
         sys: &mut System,
         scheduler_task: &mut SchedulerTask,
-        mem: &mut TaskMemory<'_, '_>,
+        mem: &mut TaskMemory<'_>,
         iterations: u32,
     ) -> Result<TaskRunResult, (TaskError, u32)> {
-        let mut ins_cache = {
-            (
-                {
-                    match mem.mem[0] {
-                        Some(page) => page,
-                        None => {
-                            return Err((
-                                TaskError::MemoryDoesNotExistError(
-                                    self.vm_state.pc,
-                                    self.vm_state.pc,
-                                ),
-                                0,
-                            ))
+        // tracking writes and delay slots are rare so the checks for them are compiled out of the
+        // usual interpreter
+        match (mem.written.is_some(), self.delay_slots) {
+            (false, DelaySlots::Skipped) => {
+                self.run_interpreter::<false, false>(sys, scheduler_task, mem, iterations)
+            }
+            (false, DelaySlots::Executed) => {
+                self.run_interpreter::<false, true>(sys, scheduler_task, mem, iterations)
+            }
+            (true, DelaySlots::Skipped) => {
+                self.run_interpreter::<true, false>(sys, scheduler_task, mem, iterations)
+            }
+            (true, DelaySlots::Executed) => {
+                self.run_interpreter::<true, true>(sys, scheduler_task, mem, iterations)
+            }
+        }
+    }
+
+    /// Hands `err`, raised by the instruction at `pc`, to the exception handler of the guest.
+    /// Returns false if the guest didn't install one or can't handle the error
+    #[cold]
+    fn raise_exception(
+        &mut self,
+        err: &TaskError,
+        pc: VmInstructionAddress,
+        in_delay_slot: bool,
+    ) -> bool {
+        if !self.vm_state.cop0.handles_exceptions() {
+            return false;
+        }
+        let Some(fault) = Fault::of(err) else {
+            return false;
+        };
+        // a fault in a delay slot returns to the branch so it runs again
+        let epc = if in_delay_slot {
+            pc.wrapping_sub(4)
+        } else {
+            pc
+        };
+        self.vm_state.delayed_branch = None;
+        self.vm_state.pc =
+            self.vm_state
+                .cop0
+                .raise(fault.code, epc, in_delay_slot, fault.bad_vaddr);
+        true
+    }
+
+    fn run_interpreter<const TRACK_WRITES: bool, const DELAY_SLOTS: bool>(
+        &mut self,
+        sys: &mut System,
+        scheduler_task: &mut SchedulerTask,
+        mem: &mut TaskMemory<'_>,
+        iterations: u32,
+    ) -> Result<TaskRunResult, (TaskError, u32)> {
+        let mut ins_cache = loop {
+            let page = match mem.executable_page(self.vm_state.pc) {
+                Some(page) => page,
+                None => match self.fault_in(sys, mem, self.vm_state.pc, MemoryAccess::Execute) {
+                    Ok(page) => page,
+                    Err(err) => {
+                        // the run can start in a delay slot, the branch it belongs to is pending
+                        let in_delay_slot = self.vm_state.delayed_branch.is_some();
+                        if self.raise_exception(&err, self.vm_state.pc, in_delay_slot) {
+                            continue;
                         }
+                        return Err((err, 0));
                     }
                 },
-                self.vm_state.pc >> 16,
-            )
+            };
+            break (page, page_number(self.vm_state.pc));
         };
 
-        for ran in 0..iterations {
+        let written = mem.written.clone();
+
+        'run: for ran in 0..iterations {
             macro_rules! set_mem_alligned {
                 ($add:expr, $val:expr, $fn_type:ty) => {
                     unsafe {
                         let address = $add;
 
-                        let page = match mem.mem[address as usize >> 16] {
-                            Some(page) => page,
-                            None => {
-                                return Err((
-                                    TaskError::MemoryDoesNotExistError(address, self.vm_state.pc),
-                                    ran,
-                                ))
-                            }
-                        };
-                        page.set_from_core_unchecked::<$fn_type>(address as u16, $val);
+                        let page = writable_page!(address);
+                        if unlikely(word_identity(page, address) == mem.ll_reservation) {
+                            mem.ll_reservation = 0;
+                        }
+                        page.set_from_core_unchecked::<$fn_type>(page_offset(address), $val);
+                        if let (true, Some(written)) = (TRACK_WRITES, &written) {
+                            written.mark(page, address);
+                        }
                     }
                 };
             }
 
+            macro_rules! writable_page {
+                ($address:expr) => {{
+                    let address = $address;
+                    match mem.mem[page_number(address) as usize] {
+                        Some(page) if mem.protection[page_number(address) as usize].writable() => {
+                            page
+                        }
+                        _ => match self.fault_in(sys, mem, address, MemoryAccess::Write) {
+                            Ok(page) => {
+                                // the page might have been the one we are executing from
+                                ins_cache.1 = u32::MAX;
+                                page
+                            }
+                            Err(err) => fault!(err),
+                        },
+                    }
+                }};
+            }
+
             macro_rules! get_mem_alligned {
                 ($add:expr, $fn_type:ty) => {
                     unsafe {
                         let address = $add;
 
-                        let page = match mem.mem[address as usize >> 16] {
-                            Some(page) => page,
-                            None => {
-                                return Err((
-                                    TaskError::MemoryDoesNotExistError(address, self.vm_state.pc),
-                                    ran,
-                                ))
+                        let page = match mem.mem[page_number(address) as usize] {
+                            Some(page)
+                                if mem.protection[page_number(address) as usize].readable() =>
+                            {
+                                page
                             }
+                            _ => match self.fault_in(sys, mem, address, MemoryAccess::Read) {
+                                Ok(page) => page,
+                                Err(err) => fault!(err),
+                            },
                         };
-                        page.load_from_core_unchecked::<$fn_type>(address as u16)
+                        if let (true, Some(written)) = (TRACK_WRITES, &written) {
+                            written.check_read(page, address, self.task_id, self.vm_state.pc);
+                        }
+                        page.load_from_core_unchecked::<$fn_type>(page_offset(address))
                     }
                 };
             }
@@ -327,37 +1080,119 @@ impl Task {
                 };
             }
 
+            macro_rules! trap {
+                ($code:expr) => {
+                    fault!(TaskError::Trap($code, self.vm_state.pc))
+                };
+            }
+
             macro_rules! breakpoint {
                 ($id:expr) => {
                     interface_call!(breakpoint, $id);
                 };
             }
 
+            // the branch this instruction is the delay slot of
+            let delayed = if DELAY_SLOTS {
+                self.vm_state.delayed_branch.take()
+            } else {
+                None
+            };
+            let op_pc = self.vm_state.pc;
+
+            // hands an error raised by this instruction to the exception handler of the guest,
+            // errors it can't handle stop the run
+            macro_rules! fault {
+                ($err:expr) => {{
+                    let err = $err;
+                    if self.raise_exception(&err, op_pc, delayed.is_some()) {
+                        continue 'run;
+                    }
+                    return Err((err, ran));
+                }};
+            }
+
             let op: u32 = unsafe {
-                if unlikely(self.vm_state.pc >> 16 != ins_cache.1) {
+                if unlikely(page_number(self.vm_state.pc) != ins_cache.1) {
                     ins_cache = (
                         {
-                            match mem.mem[self.vm_state.pc as usize >> 16] {
+                            match mem.executable_page(self.vm_state.pc) {
                                 Some(page) => page,
-                                None => {
-                                    return Err((
-                                        TaskError::MemoryDoesNotExistError(
-                                            self.vm_state.pc,
-                                            self.vm_state.pc,
-                                        ),
-                                        ran,
-                                    ))
-                                }
+                                None => match self.fault_in(
+                                    sys,
+                                    mem,
+                                    self.vm_state.pc,
+                                    MemoryAccess::Execute,
+                                ) {
+                                    Ok(page) => page,
+                                    Err(err) => fault!(err),
+                                },
                             }
                         },
-                        self.vm_state.pc >> 16,
+                        page_number(self.vm_state.pc),
                     );
                 }
 
-                (*ins_cache.0).get_u32_unchecked(self.vm_state.pc as u16)
+                (*ins_cache.0).get_u32_unchecked(page_offset(self.vm_state.pc))
             };
             self.vm_state.pc = self.vm_state.pc.wrapping_add(4);
 
+            macro_rules! jump {
+                ($target:expr) => {
+                    if DELAY_SLOTS {
+                        self.vm_state.delayed_branch = Some($target);
+                    } else {
+                        self.vm_state.pc = $target;
+                    }
+                };
+            }
+
+            macro_rules! branch {
+                ($cond:expr) => {
+                    if $cond {
+                        jump!(
+                            ((self.vm_state.pc as i32)
+                                .wrapping_add(immediate_immediate_address!(op))) as u32
+                        );
+                    } else if !DELAY_SLOTS {
+                        self.vm_state.pc += 4;
+                    }
+                };
+            }
+
+            macro_rules! branch_likely {
+                ($cond:expr) => {
+                    if $cond {
+                        jump!(
+                            ((self.vm_state.pc as i32)
+                                .wrapping_add(immediate_immediate_address!(op))) as u32
+                        );
+                    } else {
+                        // the delay slot is annulled
+                        self.vm_state.pc += 4;
+                    }
+                };
+            }
+
+            macro_rules! return_address {
+                () => {
+                    if DELAY_SLOTS {
+                        self.vm_state.pc.wrapping_add(4)
+                    } else {
+                        self.vm_state.pc
+                    }
+                };
+            }
+
+            // transfers control once the delay slot ran, also when a system call in it stops the run
+            macro_rules! finish_delay_slot {
+                () => {
+                    if let Some(target) = delayed {
+                        self.vm_state.pc = target;
+                    }
+                };
+            }
+
             macro_rules! interface_call{
                 ($kind:ident, $id:expr) => {
                     match sys.$kind($id, self, scheduler_task, mem){
@@ -369,8 +1204,11 @@ impl Task {
                                 return Err((TaskError::InvalidOperation(self.vm_state.pc, op),ran))
                             }
                         },
-                        crate::system::InterfaceCallResult::Exit => {
-                            return Ok(TaskRunResult::Exit(ran, 0))
+                        crate::system::InterfaceCallResult::Exit(code) => {
+                            return Ok(TaskRunResult::Exit(ran, code))
+                        }
+                        crate::system::InterfaceCallResult::ExitProcess(code) => {
+                            return Ok(TaskRunResult::ExitProcess(ran, code))
                         }
                         crate::system::InterfaceCallResult::InvalidCall(id) => {
                             return Err((TaskError::InvalidOperation(self.vm_state.pc, id),ran))
@@ -381,11 +1219,22 @@ impl Task {
                         }
                         crate::system::InterfaceCallResult::WaitRepeated => {
                             self.vm_state.pc -= 4; //we need to re-run this system call when we try again
+                            self.vm_state.delayed_branch = delayed;
                             return Ok(TaskRunResult::Wait(ran))
                         },
                         crate::system::InterfaceCallResult::Wait => {
+                            finish_delay_slot!();
                             return Ok(TaskRunResult::Wait(ran))
                         },
+                        crate::system::InterfaceCallResult::Block(key) => {
+                            finish_delay_slot!();
+                            return Ok(TaskRunResult::Block(ran, key))
+                        },
+                        crate::system::InterfaceCallResult::BlockRepeated(key) => {
+                            self.vm_state.pc -= 4; //we need to re-run this system call when we are woken
+                            self.vm_state.delayed_branch = delayed;
+                            return Ok(TaskRunResult::Block(ran, key))
+                        },
                     }
                 }
             }
@@ -411,7 +1260,7 @@ impl Task {
                                 }
 
                                 None => {
-                                    return Err((TaskError::OverflowError(self.vm_state.pc), ran));
+                                    fault!(TaskError::OverflowError(self.vm_state.pc));
                                 }
                             }
                         }
@@ -433,7 +1282,7 @@ impl Task {
                                 self.vm_state.lo = (s.wrapping_div(t)) as u32;
                                 self.vm_state.hi = (s.wrapping_rem(t)) as u32;
                             } else {
-                                return Err((TaskError::DivByZeroError(self.vm_state.pc), ran));
+                                fault!(TaskError::DivByZeroError(self.vm_state.pc));
                             }
                         }
                         0b011011 => {
@@ -444,7 +1293,7 @@ impl Task {
                                 self.vm_state.lo = s.wrapping_div(t);
                                 self.vm_state.hi = s.wrapping_rem(t);
                             } else {
-                                return Err((TaskError::DivByZeroError(self.vm_state.pc), ran));
+                                fault!(TaskError::DivByZeroError(self.vm_state.pc));
                             }
                         }
                         0b011000 => {
@@ -504,14 +1353,29 @@ impl Task {
                                 as u32;
                         }
                         0b000010 => {
-                            //SRL
-                            self.vm_state.reg[register_d!(op)] =
-                                self.vm_state.reg[register_t!(op)] >> register_a!(op);
+                            if op & (1 << 21) == 0 {
+                                //SRL
+                                self.vm_state.reg[register_d!(op)] =
+                                    self.vm_state.reg[register_t!(op)] >> register_a!(op);
+                            } else {
+                                //ROTR
+                                self.vm_state.reg[register_d!(op)] = self.vm_state.reg
+                                    [register_t!(op)]
+                                .rotate_right(register_a!(op));
+                            }
                         }
                         0b000110 => {
-                            //SRLV
-                            self.vm_state.reg[register_d!(op)] = self.vm_state.reg[register_t!(op)]
-                                >> (0b11111 & self.vm_state.reg[register_s!(op)]);
+                            if op & (1 << 6) == 0 {
+                                //SRLV
+                                self.vm_state.reg[register_d!(op)] = self.vm_state.reg
+                                    [register_t!(op)]
+                                    >> (0b11111 & self.vm_state.reg[register_s!(op)]);
+                            } else {
+                                //ROTRV
+                                self.vm_state.reg[register_d!(op)] = self.vm_state.reg
+                                    [register_t!(op)]
+                                .rotate_right(0b11111 & self.vm_state.reg[register_s!(op)]);
+                            }
                         }
                         0b100010 => {
                             //SUB
@@ -520,7 +1384,7 @@ impl Task {
                             {
                                 self.vm_state.reg[register_d!(op)] = val as u32;
                             } else {
-                                return Err((TaskError::OverflowError(self.vm_state.pc), ran));
+                                fault!(TaskError::OverflowError(self.vm_state.pc));
                             }
                         }
                         0b100011 => {
@@ -558,12 +1422,13 @@ impl Task {
                         //jump
                         0b001001 => {
                             //JALR
-                            self.vm_state.reg[31] = self.vm_state.pc;
-                            self.vm_state.pc = self.vm_state.reg[register_s!(op)];
+                            let target = self.vm_state.reg[register_s!(op)];
+                            self.vm_state.reg[31] = return_address!();
+                            jump!(target);
                         }
                         0b001000 => {
                             //JR
-                            self.vm_state.pc = self.vm_state.reg[register_s!(op)];
+                            jump!(self.vm_state.reg[register_s!(op)]);
                         }
 
                         //data movement
@@ -584,6 +1449,30 @@ impl Task {
                             self.vm_state.lo = self.vm_state.reg[register_s!(op)];
                         }
 
+                        //conditional moves
+                        0b000001 => {
+                            //MOVF MOVT
+                            let cc = (op >> 18) & 0b111;
+                            if self.vm_state.fpu.condition(cc) == (op & (1 << 16) != 0) {
+                                self.vm_state.reg[register_d!(op)] =
+                                    self.vm_state.reg[register_s!(op)];
+                            }
+                        }
+                        0b001010 => {
+                            //MOVZ
+                            if self.vm_state.reg[register_t!(op)] == 0 {
+                                self.vm_state.reg[register_d!(op)] =
+                                    self.vm_state.reg[register_s!(op)];
+                            }
+                        }
+                        0b001011 => {
+                            //MOVN
+                            if self.vm_state.reg[register_t!(op)] != 0 {
+                                self.vm_state.reg[register_d!(op)] =
+                                    self.vm_state.reg[register_s!(op)];
+                            }
+                        }
+
                         //special
                         0b001100 => {
                             //syscall
@@ -600,8 +1489,7 @@ impl Task {
                             if self.vm_state.reg[register_s!(op)]
                                 == self.vm_state.reg[register_t!(op)]
                             {
-                                let id = (op >> 6) & 0b1111111111;
-                                system_call!(id);
+                                trap!((op >> 6) & 0b1111111111);
                             }
                         }
                         0b110000 => {
@@ -609,8 +1497,7 @@ impl Task {
                             if self.vm_state.reg[register_s!(op)] as i32
                                 >= self.vm_state.reg[register_t!(op)] as i32
                             {
-                                let id = (op >> 6) & 0b1111111111;
-                                system_call!(id);
+                                trap!((op >> 6) & 0b1111111111);
                             }
                         }
                         0b110001 => {
@@ -618,26 +1505,23 @@ impl Task {
                             if self.vm_state.reg[register_s!(op)]
                                 >= self.vm_state.reg[register_t!(op)]
                             {
-                                let id = (op >> 6) & 0b1111111111;
-                                system_call!(id);
+                                trap!((op >> 6) & 0b1111111111);
                             }
                         }
                         0b110010 => {
-                            //TIT
+                            //TLT
                             if (self.vm_state.reg[register_s!(op)] as i32)
                                 < self.vm_state.reg[register_t!(op)] as i32
                             {
-                                let id = (op >> 6) & 0b1111111111;
-                                system_call!(id);
+                                trap!((op >> 6) & 0b1111111111);
                             }
                         }
                         0b110011 => {
-                            //TITU
+                            //TLTU
                             if self.vm_state.reg[register_s!(op)]
                                 < self.vm_state.reg[register_t!(op)]
                             {
-                                let id = (op >> 6) & 0b1111111111;
-                                system_call!(id);
+                                trap!((op >> 6) & 0b1111111111);
                             }
                         }
                         0b110110 => {
@@ -645,25 +1529,199 @@ impl Task {
                             if self.vm_state.reg[register_s!(op)]
                                 != self.vm_state.reg[register_t!(op)]
                             {
-                                let id = (op >> 6) & 0b1111111111;
-                                system_call!(id);
+                                trap!((op >> 6) & 0b1111111111);
                             }
                         }
 
-                        _ => return Err((TaskError::InvalidOperation(self.vm_state.pc, op), ran)),
+                        _ => fault!(TaskError::InvalidOperation(self.vm_state.pc, op)),
+                    }
+                }
+                0b011100 => {
+                    match op & 0b111111 {
+                        // SPECIAL2 instructions
+
+                        //arithmatic
+                        0b000010 => {
+                            //MUL
+                            self.vm_state.reg[register_d!(op)] = (self.vm_state.reg[register_s!(op)]
+                                as i32)
+                                .wrapping_mul(self.vm_state.reg[register_t!(op)] as i32)
+                                as u32;
+                        }
+                        0b000000 => {
+                            //MADD
+                            let t = self.vm_state.reg[register_t!(op)] as i32 as i64;
+                            let s = self.vm_state.reg[register_s!(op)] as i32 as i64;
+                            let acc = ((self.vm_state.hi as u64) << 32 | self.vm_state.lo as u64)
+                                .wrapping_add(s.wrapping_mul(t) as u64);
+                            self.vm_state.lo = (acc & 0xFFFFFFFF) as u32;
+                            self.vm_state.hi = (acc >> 32) as u32;
+                        }
+                        0b000001 => {
+                            //MADDU
+                            let t = self.vm_state.reg[register_t!(op)] as u64;
+                            let s = self.vm_state.reg[register_s!(op)] as u64;
+                            let acc = ((self.vm_state.hi as u64) << 32 | self.vm_state.lo as u64)
+                                .wrapping_add(s.wrapping_mul(t));
+                            self.vm_state.lo = (acc & 0xFFFFFFFF) as u32;
+                            self.vm_state.hi = (acc >> 32) as u32;
+                        }
+                        0b000100 => {
+                            //MSUB
+                            let t = self.vm_state.reg[register_t!(op)] as i32 as i64;
+                            let s = self.vm_state.reg[register_s!(op)] as i32 as i64;
+                            let acc = ((self.vm_state.hi as u64) << 32 | self.vm_state.lo as u64)
+                                .wrapping_sub(s.wrapping_mul(t) as u64);
+                            self.vm_state.lo = (acc & 0xFFFFFFFF) as u32;
+                            self.vm_state.hi = (acc >> 32) as u32;
+                        }
+                        0b000101 => {
+                            //MSUBU
+                            let t = self.vm_state.reg[register_t!(op)] as u64;
+                            let s = self.vm_state.reg[register_s!(op)] as u64;
+                            let acc = ((self.vm_state.hi as u64) << 32 | self.vm_state.lo as u64)
+                                .wrapping_sub(s.wrapping_mul(t));
+                            self.vm_state.lo = (acc & 0xFFFFFFFF) as u32;
+                            self.vm_state.hi = (acc >> 32) as u32;
+                        }
+
+                        //bit counting
+                        0b100000 => {
+                            //CLZ
+                            self.vm_state.reg[register_d!(op)] =
+                                self.vm_state.reg[register_s!(op)].leading_zeros();
+                        }
+                        0b100001 => {
+                            //CLO
+                            self.vm_state.reg[register_d!(op)] =
+                                self.vm_state.reg[register_s!(op)].leading_ones();
+                        }
+
+                        _ => fault!(TaskError::InvalidOperation(self.vm_state.pc, op)),
+                    }
+                }
+                0b011111 => {
+                    match op & 0b111111 {
+                        // SPECIAL3 instructions
+
+                        //bit fields
+                        0b000000 => {
+                            //EXT
+                            let pos = register_a!(op);
+                            let size = register_d!(op) as u32 + 1;
+                            if pos + size > 32 {
+                                fault!(TaskError::InvalidOperation(self.vm_state.pc, op));
+                            }
+                            self.vm_state.reg[register_t!(op)] =
+                                (self.vm_state.reg[register_s!(op)] >> pos)
+                                    & (u32::MAX >> (32 - size));
+                        }
+                        0b000100 => {
+                            //INS
+                            let pos = register_a!(op);
+                            let msb = register_d!(op) as u32;
+                            if msb < pos {
+                                fault!(TaskError::InvalidOperation(self.vm_state.pc, op));
+                            }
+                            let mask = (u32::MAX >> (31 - (msb - pos))) << pos;
+                            self.vm_state.reg[register_t!(op)] =
+                                (self.vm_state.reg[register_t!(op)] & !mask)
+                                    | ((self.vm_state.reg[register_s!(op)] << pos) & mask);
+                        }
+
+                        0b100000 => match register_a!(op) {
+                            0b00010 => {
+                                //WSBH
+                                let t = self.vm_state.reg[register_t!(op)];
+                                self.vm_state.reg[register_d!(op)] =
+                                    ((t & 0x00FF00FF) << 8) | ((t >> 8) & 0x00FF00FF);
+                            }
+                            0b10000 => {
+                                //SEB
+                                self.vm_state.reg[register_d!(op)] =
+                                    self.vm_state.reg[register_t!(op)] as i8 as i32 as u32;
+                            }
+                            0b11000 => {
+                                //SEH
+                                self.vm_state.reg[register_d!(op)] =
+                                    self.vm_state.reg[register_t!(op)] as i16 as i32 as u32;
+                            }
+                            _ => {
+                                fault!(TaskError::InvalidOperation(self.vm_state.pc, op))
+                            }
+                        },
+
+                        _ => fault!(TaskError::InvalidOperation(self.vm_state.pc, op)),
+                    }
+                }
+                0b010000 => {
+                    match immediate_s!(op) {
+                        // COP0 instructions
+                        0b00000 => {
+                            //MFC0
+                            match self.vm_state.cop0.read(register_d!(op), op & 0b111) {
+                                Some(val) => self.vm_state.reg[register_t!(op)] = val,
+                                None => {
+                                    fault!(TaskError::InvalidOperation(self.vm_state.pc, op))
+                                }
+                            }
+                        }
+                        0b00100 => {
+                            //MTC0
+                            let val = self.vm_state.reg[register_t!(op)];
+                            if self
+                                .vm_state
+                                .cop0
+                                .write(register_d!(op), op & 0b111, val)
+                                .is_none()
+                            {
+                                fault!(TaskError::InvalidOperation(self.vm_state.pc, op));
+                            }
+                        }
+                        0b10000 if op & 0b111111 == 0b011000 => {
+                            //ERET
+                            self.vm_state.pc = self.vm_state.cop0.eret();
+                            mem.ll_reservation = 0;
+                        }
+                        _ => fault!(TaskError::InvalidOperation(self.vm_state.pc, op)),
+                    }
+                }
+                0b010001 => {
+                    if immediate_s!(op) == 0b01000 {
+                        //BC1F BC1T BC1FL BC1TL
+                        let cc = (op >> 18) & 0b111;
+                        let taken = self.vm_state.fpu.condition(cc) == (op & (1 << 16) != 0);
+                        if op & (1 << 17) == 0 {
+                            branch!(taken);
+                        } else {
+                            branch_likely!(taken);
+                        }
+                    } else if let Err(err) = self.vm_state.fpu.execute(op, &mut self.vm_state.reg) {
+                        let err = match err {
+                            Cop1Error::Exception(raised) => {
+                                TaskError::FloatingPointException(raised, self.vm_state.pc)
+                            }
+                            Cop1Error::Reserved => {
+                                TaskError::InvalidOperation(self.vm_state.pc, op)
+                            }
+                        };
+                        fault!(err);
                     }
                 }
                 //Jump instructions
                 0b000010 => {
                     //jump
-                    self.vm_state.pc = (self.vm_state.pc & 0b11110000000000000000000000000000)
-                        | jump_immediate_address!(op);
+                    jump!(
+                        (self.vm_state.pc & 0b11110000000000000000000000000000)
+                            | jump_immediate_address!(op)
+                    );
                 }
                 0b000011 => {
                     //jal
-                    self.vm_state.reg[31] = self.vm_state.pc;
-                    self.vm_state.pc = (self.vm_state.pc & 0b11110000000000000000000000000000)
+                    let target = (self.vm_state.pc & 0b11110000000000000000000000000000)
                         | jump_immediate_address!(op);
+                    self.vm_state.reg[31] = return_address!();
+                    jump!(target);
                 }
                 // IMMEDIATE formmated instructions
 
@@ -675,7 +1733,7 @@ impl Task {
                     {
                         self.vm_state.reg[immediate_t!(op)] = val as u32;
                     } else {
-                        return Err((TaskError::OverflowError(self.vm_state.pc), ran));
+                        fault!(TaskError::OverflowError(self.vm_state.pc));
                     }
                 }
                 0b001001 => {
@@ -735,69 +1793,137 @@ impl Task {
                 // branch instructions
                 0b000100 => {
                     //BEQ
-                    if self.vm_state.reg[immediate_s!(op)] == self.vm_state.reg[immediate_t!(op)] {
-                        self.vm_state.pc = ((self.vm_state.pc as i32)
-                            .wrapping_add(immediate_immediate_address!(op)))
-                            as u32;
-                    } else {
-                        self.vm_state.pc += 4;
-                    }
+                    branch!(
+                        self.vm_state.reg[immediate_s!(op)] == self.vm_state.reg[immediate_t!(op)]
+                    );
                 }
                 0b000001 => {
                     match immediate_t!(op) {
                         0b00001 => {
                             //BGEZ
-                            if (self.vm_state.reg[immediate_s!(op)] as i32) >= 0 {
-                                self.vm_state.pc = ((self.vm_state.pc as i32)
-                                    .wrapping_add(immediate_immediate_address!(op)))
-                                    as u32;
-                            } else {
-                                self.vm_state.pc += 4;
-                            }
+                            branch!((self.vm_state.reg[immediate_s!(op)] as i32) >= 0);
                         }
                         0b00000 => {
                             //BLTZ
-                            if (self.vm_state.reg[immediate_s!(op)] as i32) < 0 {
-                                self.vm_state.pc = ((self.vm_state.pc as i32)
-                                    .wrapping_add(immediate_immediate_address!(op)))
-                                    as u32;
-                            } else {
-                                self.vm_state.pc += 4;
+                            branch!((self.vm_state.reg[immediate_s!(op)] as i32) < 0);
+                        }
+                        0b00011 => {
+                            //BGEZL
+                            branch_likely!((self.vm_state.reg[immediate_s!(op)] as i32) >= 0);
+                        }
+                        0b00010 => {
+                            //BLTZL
+                            branch_likely!((self.vm_state.reg[immediate_s!(op)] as i32) < 0);
+                        }
+                        0b01100 => {
+                            //TEQI
+                            if self.vm_state.reg[immediate_s!(op)]
+                                == immediate_immediate_signed_extended!(op)
+                            {
+                                trap!(0);
+                            }
+                        }
+                        0b01000 => {
+                            //TGEI
+                            if self.vm_state.reg[immediate_s!(op)] as i32
+                                >= immediate_immediate_signed_extended!(op) as i32
+                            {
+                                trap!(0);
+                            }
+                        }
+                        0b01001 => {
+                            //TGEIU
+                            if self.vm_state.reg[immediate_s!(op)]
+                                >= immediate_immediate_signed_extended!(op)
+                            {
+                                trap!(0);
+                            }
+                        }
+                        0b01010 => {
+                            //TLTI
+                            if (self.vm_state.reg[immediate_s!(op)] as i32)
+                                < immediate_immediate_signed_extended!(op) as i32
+                            {
+                                trap!(0);
+                            }
+                        }
+                        0b01011 => {
+                            //TLTIU
+                            if self.vm_state.reg[immediate_s!(op)]
+                                < immediate_immediate_signed_extended!(op)
+                            {
+                                trap!(0);
+                            }
+                        }
+                        0b01110 => {
+                            //TNEI
+                            if self.vm_state.reg[immediate_s!(op)]
+                                != immediate_immediate_signed_extended!(op)
+                            {
+                                trap!(0);
                             }
                         }
-                        _ => return Err((TaskError::InvalidOperation(self.vm_state.pc, op), ran)),
+                        0b10001 => {
+                            //BGEZAL
+                            let taken = (self.vm_state.reg[immediate_s!(op)] as i32) >= 0;
+                            self.vm_state.reg[31] = return_address!();
+                            branch!(taken);
+                        }
+                        0b10000 => {
+                            //BLTZAL
+                            let taken = (self.vm_state.reg[immediate_s!(op)] as i32) < 0;
+                            self.vm_state.reg[31] = return_address!();
+                            branch!(taken);
+                        }
+                        0b10011 => {
+                            //BGEZALL
+                            let taken = (self.vm_state.reg[immediate_s!(op)] as i32) >= 0;
+                            self.vm_state.reg[31] = return_address!();
+                            branch_likely!(taken);
+                        }
+                        0b10010 => {
+                            //BLTZALL
+                            let taken = (self.vm_state.reg[immediate_s!(op)] as i32) < 0;
+                            self.vm_state.reg[31] = return_address!();
+                            branch_likely!(taken);
+                        }
+                        _ => fault!(TaskError::InvalidOperation(self.vm_state.pc, op)),
                     }
                 }
                 0b000111 => {
                     //BGTZ
-                    if self.vm_state.reg[immediate_s!(op)] as i32 > 0 {
-                        self.vm_state.pc = ((self.vm_state.pc as i32)
-                            .wrapping_add(immediate_immediate_address!(op)))
-                            as u32;
-                    } else {
-                        self.vm_state.pc += 4;
-                    }
+                    branch!(self.vm_state.reg[immediate_s!(op)] as i32 > 0);
                 }
 
                 0b000110 => {
                     //BLEZ
-                    if self.vm_state.reg[immediate_s!(op)] as i32 <= 0 {
-                        self.vm_state.pc = ((self.vm_state.pc as i32)
-                            .wrapping_add(immediate_immediate_address!(op)))
-                            as u32;
-                    } else {
-                        self.vm_state.pc += 4;
-                    }
+                    branch!(self.vm_state.reg[immediate_s!(op)] as i32 <= 0);
                 }
                 0b000101 => {
                     //BNE
-                    if self.vm_state.reg[immediate_s!(op)] != self.vm_state.reg[immediate_t!(op)] {
-                        self.vm_state.pc = ((self.vm_state.pc as i32)
-                            .wrapping_add(immediate_immediate_address!(op)))
-                            as u32;
-                    } else {
-                        self.vm_state.pc += 4;
-                    }
+                    branch!(
+                        self.vm_state.reg[immediate_s!(op)] != self.vm_state.reg[immediate_t!(op)]
+                    );
+                }
+                0b010100 => {
+                    //BEQL
+                    branch_likely!(
+                        self.vm_state.reg[immediate_s!(op)] == self.vm_state.reg[immediate_t!(op)]
+                    );
+                }
+                0b010101 => {
+                    //BNEL
+                    branch_likely!(
+                        self.vm_state.reg[immediate_s!(op)] != self.vm_state.reg[immediate_t!(op)]
+                    );
+                }
+                0b010111 => {
+                    //BGTZL
+                    branch_likely!(self.vm_state.reg[immediate_s!(op)] as i32 > 0);
+                }
+                0b010110 => {
+                    //BLEZL
+                    branch_likely!(self.vm_state.reg[immediate_s!(op)] as i32 <= 0);
                 }
 
                 //load unsinged instructions
@@ -879,7 +2005,12 @@ impl Task {
                             get_mem_alligned!(address, i16) as u32;
                     //self.mem.get_i16_alligned(address) as u32
                     } else {
-                        return Err((TaskError::MemoryAllignmentError(2, self.vm_state.pc), ran));
+                        fault!(TaskError::MemoryAllignmentError(
+                            2,
+                            MemoryAccess::Read,
+                            address,
+                            self.vm_state.pc
+                        ));
                     }
                 }
                 0b100101 => {
@@ -893,7 +2024,12 @@ impl Task {
                             get_mem_alligned!(address, u16) as u32;
                     //self.mem.get_u16_alligned(address) as u32
                     } else {
-                        return Err((TaskError::MemoryAllignmentError(2, self.vm_state.pc), ran));
+                        fault!(TaskError::MemoryAllignmentError(
+                            2,
+                            MemoryAccess::Read,
+                            address,
+                            self.vm_state.pc
+                        ));
                     }
                 }
                 0b100011 => {
@@ -906,7 +2042,12 @@ impl Task {
                         self.vm_state.reg[immediate_t!(op)] = get_mem_alligned!(address, u32);
                     //self.mem.get_u32_alligned(address) as u32
                     } else {
-                        return Err((TaskError::MemoryAllignmentError(4, self.vm_state.pc), ran));
+                        fault!(TaskError::MemoryAllignmentError(
+                            4,
+                            MemoryAccess::Read,
+                            address,
+                            self.vm_state.pc
+                        ));
                     }
                 }
 
@@ -917,11 +2058,16 @@ impl Task {
                         as u32;
 
                     if likely(address & 0b11 == 0) {
-                        mem.ll_bit.store(true, std::sync::atomic::Ordering::Release);
                         self.vm_state.reg[immediate_t!(op)] = get_mem_alligned!(address, u32);
-                    //self.mem.get_u32_alligned(address) as u32
+                        // the load succeeded so the page is mapped
+                        mem.ll_reservation = word_identity(mem.page(address).unwrap(), address);
                     } else {
-                        return Err((TaskError::MemoryAllignmentError(4, self.vm_state.pc), ran));
+                        fault!(TaskError::MemoryAllignmentError(
+                            4,
+                            MemoryAccess::Read,
+                            address,
+                            self.vm_state.pc
+                        ));
                     }
                 }
                 0b111000 => {
@@ -931,17 +2077,115 @@ impl Task {
                         as u32;
 
                     if likely(address & 0b11 == 0) {
-                        if mem.ll_bit.load(std::sync::atomic::Ordering::Acquire) {
-                            set_mem_alligned!(address, self.vm_state.reg[immediate_t!(op)], u32);
+                        let page = writable_page!(address);
+                        if word_identity(page, address) == mem.ll_reservation {
+                            unsafe {
+                                page.set_from_core_unchecked::<u32>(
+                                    page_offset(address),
+                                    self.vm_state.reg[immediate_t!(op)],
+                                )
+                            };
+                            if let (true, Some(written)) = (TRACK_WRITES, &written) {
+                                written.mark(page, address);
+                            }
                             self.vm_state.reg[immediate_t!(op)] = 1;
                         } else {
                             self.vm_state.reg[immediate_t!(op)] = 0;
                         }
-                        mem.ll_bit
-                            .store(false, std::sync::atomic::Ordering::Release);
+                        mem.ll_reservation = 0;
                     } else {
                         self.vm_state.reg[immediate_t!(op)] = 0;
-                        return Err((TaskError::MemoryAllignmentError(4, self.vm_state.pc), ran));
+                        fault!(TaskError::MemoryAllignmentError(
+                            4,
+                            MemoryAccess::Write,
+                            address,
+                            self.vm_state.pc
+                        ));
+                    }
+                }
+
+                // floating point loads and stores
+                0b110001 => {
+                    //LWC1
+                    let address = ((self.vm_state.reg[immediate_s!(op)] as i32)
+                        .wrapping_add(immediate_immediate_signed_extended!(op) as i32))
+                        as u32;
+
+                    if likely(address & 0b11 == 0) {
+                        self.vm_state.fpu.fpr[immediate_t!(op)] = get_mem_alligned!(address, u32);
+                    } else {
+                        fault!(TaskError::MemoryAllignmentError(
+                            4,
+                            MemoryAccess::Read,
+                            address,
+                            self.vm_state.pc
+                        ));
+                    }
+                }
+                0b110101 => {
+                    //LDC1
+                    let address = ((self.vm_state.reg[immediate_s!(op)] as i32)
+                        .wrapping_add(immediate_immediate_signed_extended!(op) as i32))
+                        as u32;
+                    let reg = immediate_t!(op);
+
+                    if reg & 1 != 0 {
+                        fault!(TaskError::InvalidOperation(self.vm_state.pc, op));
+                    }
+                    if likely(address & 0b111 == 0) {
+                        self.vm_state.fpu.fpr[reg] = get_mem_alligned!(address, u32);
+                        self.vm_state.fpu.fpr[reg + 1] =
+                            get_mem_alligned!(address.wrapping_add(4), u32);
+                    } else {
+                        fault!(TaskError::MemoryAllignmentError(
+                            8,
+                            MemoryAccess::Read,
+                            address,
+                            self.vm_state.pc
+                        ));
+                    }
+                }
+                0b111001 => {
+                    //SWC1
+                    let address = ((self.vm_state.reg[immediate_s!(op)] as i32)
+                        .wrapping_add(immediate_immediate_signed_extended!(op) as i32))
+                        as u32;
+
+                    if likely(address & 0b11 == 0) {
+                        set_mem_alligned!(address, self.vm_state.fpu.fpr[immediate_t!(op)], u32);
+                    } else {
+                        fault!(TaskError::MemoryAllignmentError(
+                            4,
+                            MemoryAccess::Write,
+                            address,
+                            self.vm_state.pc
+                        ));
+                    }
+                }
+                0b111101 => {
+                    //SDC1
+                    let address = ((self.vm_state.reg[immediate_s!(op)] as i32)
+                        .wrapping_add(immediate_immediate_signed_extended!(op) as i32))
+                        as u32;
+                    let reg = immediate_t!(op);
+
+                    if reg & 1 != 0 {
+                        fault!(TaskError::InvalidOperation(self.vm_state.pc, op));
+                    }
+                    if likely(address & 0b111 == 0) {
+                        set_mem_alligned!(address, self.vm_state.fpu.fpr[reg], u32);
+                        set_mem_alligned!(
+                            address.wrapping_add(4),
+                            self.vm_state.fpu.fpr[reg + 1],
+                            u32
+                        );
+                    } else {
+                        fault!(TaskError::MemoryAllignmentError(
+                            8,
+                            MemoryAccess::Write,
+                            address,
+                            self.vm_state.pc
+                        ));
                     }
                 }
 
@@ -952,8 +2196,6 @@ impl Task {
                         .wrapping_add(immediate_immediate_signed_extended!(op) as i32))
                         as u32;
 
-                    mem.ll_bit
-                        .store(false, std::sync::atomic::Ordering::Release);
                     set_mem_alligned!(address, self.vm_state.reg[immediate_t!(op)] as u8, u8);
                 }
                 0b101001 => {
@@ -963,11 +2205,14 @@ impl Task {
                         as u32;
 
                     if likely(address & 0b1 == 0) {
-                        mem.ll_bit
-                            .store(false, std::sync::atomic::Ordering::Release);
                         set_mem_alligned!(address, self.vm_state.reg[immediate_t!(op)] as u16, u16);
                     } else {
-                        return Err((TaskError::MemoryAllignmentError(2, self.vm_state.pc), ran));
+                        fault!(TaskError::MemoryAllignmentError(
+                            2,
+                            MemoryAccess::Write,
+                            address,
+                            self.vm_state.pc
+                        ));
                     }
                 }
                 0b101011 => {
@@ -976,17 +2221,419 @@ impl Task {
                         .wrapping_add(immediate_immediate_signed_extended!(op) as i32))
                         as u32;
                     if likely(address & 0b11 == 0) {
-                        mem.ll_bit
-                            .store(false, std::sync::atomic::Ordering::Release);
                         set_mem_alligned!(address, self.vm_state.reg[immediate_t!(op)], u32);
                     } else {
-                        return Err((TaskError::MemoryAllignmentError(4, self.vm_state.pc), ran));
+                        fault!(TaskError::MemoryAllignmentError(
+                            4,
+                            MemoryAccess::Write,
+                            address,
+                            self.vm_state.pc
+                        ));
                     }
                 }
 
-                _ => return Err((TaskError::InvalidOperation(self.vm_state.pc, op), ran)),
+                _ => fault!(TaskError::InvalidOperation(self.vm_state.pc, op)),
             }
+
+            finish_delay_slot!();
         }
         Ok(TaskRunResult::Continue)
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{
+        loader::Program,
+        system::{
+            builtin::{
+                CLONE_PROCESS, EXIT_PROCESS, FUTEX_WAIT, FUTEX_WAKE, PROCESS_WAIT, SLEEP_NANOS,
+                START_NEW_THREAD,
+            },
+            ProcessExitStatus,
+        },
+    };
+
+    const A0: u32 = 4;
+    const T0: u32 = 8;
+    const T1: u32 = 9;
+    const RA: u32 = 31;
+    const NOP: u32 = 0;
+
+    fn i_type(opcode: u32, rs: u32, rt: u32, imm: u16) -> u32 {
+        opcode << 26 | rs << 21 | rt << 16 | imm as u32
+    }
+
+    fn addiu(rt: u32, rs: u32, imm: i16) -> u32 {
+        i_type(0b001001, rs, rt, imm as u16)
+    }
+
+    /// Branch `offset` instructions past the delay slot
+    fn branch(opcode: u32, rs: u32, rt: u32, offset: i16) -> u32 {
+        i_type(opcode, rs, rt, offset as u16)
+    }
+
+    const BEQ: u32 = 0b000100;
+    const BNE: u32 = 0b000101;
+    const BNEL: u32 = 0b010101;
+
+    /// Ends the process with the value of `reg` as its exit code
+    fn exit_with(reg: u32) -> [u32; 2] {
+        [addiu(A0, reg, 0), EXIT_PROCESS << 6 | 0b001100]
+    }
+
+    /// Runs `code` as a flat binary loaded at address 0 and returns how its process ended
+    fn run(code: &[u32], delay_slots: DelaySlots) -> ProcessExitStatus {
+        let data: Vec<u8> = code.iter().flat_map(|op| op.to_le_bytes()).collect();
+        let mut sys = System::builder().build();
+        let tid = sys.add_program(&Program::from_raw(&data));
+        sys.set_delay_slots(tid.to_pid(), delay_slots);
+        let mut report = sys.run_blocking();
+        assert_eq!(report.processes.len(), 1);
+        report.processes.remove(0).status
+    }
+
+    fn exit_code(status: ProcessExitStatus) -> u32 {
+        match status {
+            ProcessExitStatus::Exited(code) => code,
+            status => panic!("{status:?}"),
+        }
+    }
+
+    /// The exit code `code` ends with in both delay slot modes, skipped first
+    fn exit_codes(code: &[u32]) -> (u32, u32) {
+        (
+            exit_code(run(code, DelaySlots::Skipped)),
+            exit_code(run(code, DelaySlots::Executed)),
+        )
+    }
+
+    #[test]
+    fn taken_branches_run_the_delay_slot_only_when_executed() {
+        let code = [
+            vec![branch(BEQ, 0, 0, 2), addiu(T0, T0, 1), addiu(T0, T0, 2)],
+            exit_with(T0).to_vec(),
+        ]
+        .concat();
+        assert_eq!(exit_codes(&code), (0, 1));
+    }
+
+    #[test]
+    fn untaken_branches_skip_or_run_the_delay_slot() {
+        let code = |opcode| {
+            [
+                vec![branch(opcode, 0, 0, 1), addiu(T0, T0, 1), addiu(T0, T0, 2)],
+                exit_with(T0).to_vec(),
+            ]
+            .concat()
+        };
+        // a branch likely nullifies the slot when it isn't taken
+        assert_eq!(exit_codes(&code(BNE)), (2, 3));
+        assert_eq!(exit_codes(&code(BNEL)), (2, 2));
+    }
+
+    #[test]
+    fn links_return_past_the_delay_slot_only_when_it_runs() {
+        // JAL 3, a skipped delay slot only ever holds a nop so returning into it is fine
+        let code = [vec![NOP, 0b000011 << 26 | 3, NOP], exit_with(RA).to_vec()].concat();
+        assert_eq!(exit_codes(&code), (8, 12));
+    }
+
+    #[test]
+    fn a_branch_in_a_delay_slot_follows_the_first_target_instruction() {
+        let code = [
+            vec![
+                branch(BEQ, 0, 0, 3),
+                branch(BEQ, 0, 0, 4),
+                addiu(T0, T0, 100),
+                addiu(T0, T0, 100),
+                addiu(T0, T0, 1),
+                addiu(T0, T0, 10),
+            ],
+            exit_with(T0).to_vec(),
+        ]
+        .concat();
+        assert_eq!(exit_codes(&code), (11, 1));
+    }
+
+    /// Loads `value` into `reg`
+    fn li(reg: u32, value: u32) -> [u32; 2] {
+        [
+            i_type(0b001111, 0, reg, (value >> 16) as u16),
+            i_type(0b001101, reg, reg, value as u16),
+        ]
+    }
+
+    /// Runs `op` with T0 = `value`, ending the process with T0 afterwards
+    fn r2(op: u32, value: u32) -> ProcessExitStatus {
+        let code = [li(T0, value).to_vec(), vec![op], exit_with(T0).to_vec()].concat();
+        run(&code, DelaySlots::Skipped)
+    }
+
+    fn special3(rs: u32, rt: u32, rd: u32, sa: u32, funct: u32) -> u32 {
+        0b011111 << 26 | rs << 21 | rt << 16 | rd << 11 | sa << 6 | funct
+    }
+
+    #[test]
+    fn r2_bit_field_and_byte_instructions() {
+        let value = 0x1234_8681;
+        let r2 = |op| exit_code(r2(op, value));
+        let bshfl = |sa| special3(0, T0, T0, sa, 0b100000);
+        // EXT T0, T0, 4, 8 and INS T0, $zero, 8, 16
+        assert_eq!(r2(special3(T0, T0, 7, 4, 0b000000)), 0x68);
+        assert_eq!(r2(special3(0, T0, 23, 8, 0b000100)), 0x1200_0081);
+        // WSBH, SEB and SEH
+        assert_eq!(r2(bshfl(0b00010)), 0x3412_8186);
+        assert_eq!(r2(bshfl(0b10000)), 0xFFFF_FF81);
+        assert_eq!(r2(bshfl(0b11000)), 0xFFFF_8681);
+        // ROTR T0, T0, 8 and CLZ T0, T0
+        assert_eq!(
+            r2(1 << 21 | T0 << 16 | T0 << 11 | 8 << 6 | 0b000010),
+            0x8112_3486
+        );
+        assert_eq!(
+            r2(0b011100 << 26 | T0 << 21 | T0 << 16 | T0 << 11 | 0b100000),
+            3
+        );
+    }
+
+    #[test]
+    fn r2_bit_fields_past_the_register_are_invalid() {
+        // EXT T0, T0, 30, 4
+        let ext = special3(T0, T0, 3, 30, 0b000000);
+        assert!(matches!(
+            r2(ext, 0),
+            ProcessExitStatus::Faulted(TaskError::InvalidOperation(_, op)) if op == ext
+        ));
+    }
+
+    const EBASE: u32 = 0x1000;
+
+    fn mfc0(rt: u32, rd: u32) -> u32 {
+        0b010000 << 26 | rt << 16 | rd << 11
+    }
+
+    fn mtc0(rt: u32, rd: u32, select: u32) -> u32 {
+        0b010000 << 26 | 0b00100 << 21 | rt << 16 | rd << 11 | select
+    }
+
+    /// Installs `handler` as the general exception handler and then runs `body`, which starts at
+    /// address 12
+    fn with_handler(body: &[u32], handler: &[u32]) -> Vec<u32> {
+        let mut code = [li(T0, EBASE).to_vec(), vec![mtc0(T0, 15, 1)], body.to_vec()].concat();
+        code.resize(((EBASE + cop0::EXCEPTION_VECTOR_OFFSET) / 4) as usize, NOP);
+        code.extend(handler);
+        code
+    }
+
+    /// Stores to the unmapped address 0x100000 at address 24, in the delay slot of a taken branch
+    /// if `in_delay_slot`
+    fn store_fault(in_delay_slot: bool) -> Vec<u32> {
+        let store = i_type(0b101011, T1, 0, 0);
+        let first = if in_delay_slot {
+            branch(BEQ, 0, 0, 2)
+        } else {
+            NOP
+        };
+        let body = [
+            li(T1, 0x10_0000).to_vec(),
+            vec![first, store, NOP],
+            exit_with(0).to_vec(),
+        ];
+        // exits with Cause | EPC << 8
+        let handler = [
+            mfc0(T0, 13),
+            mfc0(T1, 14),
+            T1 << 16 | T1 << 11 | 8 << 6,
+            T0 << 21 | T1 << 16 | T0 << 11 | 0b100101,
+        ];
+        with_handler(
+            &body.concat(),
+            &[handler.to_vec(), exit_with(T0).to_vec()].concat(),
+        )
+    }
+
+    #[test]
+    fn faults_go_to_the_guest_handler() {
+        let tlb_store = (cop0::ExceptionCode::TlbStore as u32) << 2;
+        assert_eq!(
+            exit_codes(&store_fault(false)),
+            (tlb_store | 24 << 8, tlb_store | 24 << 8)
+        );
+        // only an executed delay slot is reported as one, with the branch as EPC
+        assert_eq!(
+            exit_codes(&store_fault(true)),
+            (0, 1 << 31 | tlb_store | 20 << 8)
+        );
+    }
+
+    #[test]
+    fn eret_resumes_at_epc() {
+        let body = [
+            li(T1, 0x10_0000).to_vec(),
+            vec![i_type(0b101011, T1, 0, 0), addiu(T0, 0, 7)],
+            exit_with(T0).to_vec(),
+        ];
+        // skip the faulting store
+        let handler = [mfc0(T1, 14), addiu(T1, T1, 4), mtc0(T1, 14, 0), 0x4200_0018];
+        let code = with_handler(&body.concat(), &handler);
+        assert_eq!(exit_codes(&code), (7, 7));
+    }
+
+    #[test]
+    fn faults_without_a_handler_end_the_process() {
+        let mut code = store_fault(false);
+        // no EBase, the error holds the pc the store left behind
+        code[2] = NOP;
+        assert!(matches!(
+            run(&code, DelaySlots::Skipped),
+            ProcessExitStatus::Faulted(TaskError::MemoryDoesNotExistError(
+                MemoryAccess::Write,
+                0x10_0000,
+                28
+            ))
+        ));
+    }
+
+    #[test]
+    fn futex_waiters_follow_their_process_to_a_copied_page() {
+        const V0: u32 = 2;
+        const A1: u32 = 5;
+        let syscall = |id: u32| id << 6 | 0b001100;
+        let sleep =
+            |nanos: u32| [&li(A0, nanos)[..], &[addiu(A1, 0, 0), syscall(SLEEP_NANOS)]].concat();
+        // the futex word and the thread that waits on it sit past the main thread
+        let (word, waiter) = (0x100, 0x80);
+        let mut code = [
+            vec![addiu(A0, 0, waiter), syscall(START_NEW_THREAD)],
+            sleep(10_000_000),
+            vec![syscall(CLONE_PROCESS), branch(BEQ, V0, 0, 8), NOP],
+            // the parent writes the word, which copies the page, and wakes the waiter
+            vec![
+                addiu(T0, 0, 1),
+                i_type(0b101011, 0, T0, word as u16),
+                addiu(A0, 0, word),
+                addiu(A1, 0, 1),
+                syscall(FUTEX_WAKE),
+            ],
+            exit_with(V0).to_vec(),
+            // the child keeps sharing the page until the parent is done
+            sleep(50_000_000),
+            exit_with(0).to_vec(),
+        ]
+        .concat();
+        code.resize(waiter as usize / 4, NOP);
+        code.extend([
+            addiu(A0, 0, word),
+            addiu(A1, 0, 0),
+            syscall(FUTEX_WAIT),
+            branch(BEQ, 0, 0, -1),
+            NOP,
+        ]);
+        code.resize(word as usize / 4 + 1, NOP);
+
+        let data: Vec<u8> = code.iter().flat_map(|op| op.to_le_bytes()).collect();
+        let mut sys = System::builder().build();
+        let pid = sys.add_program(&Program::from_raw(&data)).to_pid();
+        let mut report = sys.run_blocking();
+        let parent = report
+            .processes
+            .iter()
+            .position(|process| process.pid == pid);
+        // the waiter is only found on the copy the parent made
+        assert_eq!(
+            exit_code(report.processes.swap_remove(parent.unwrap()).status),
+            1
+        );
+    }
+
+    #[test]
+    fn waiting_for_a_process_returns_its_exit_code_once() {
+        const V0: u32 = 2;
+        const V1: u32 = 3;
+        const A1: u32 = 5;
+        let syscall = |id: u32| id << 6 | 0b001100;
+        let code = [
+            vec![syscall(CLONE_PROCESS), branch(BEQ, V0, 0, 8), NOP],
+            // the parent waits twice, the second wait finds nothing left to wait for
+            vec![
+                addiu(A0, V0, 0),
+                syscall(PROCESS_WAIT),
+                addiu(T0, V0, 0),
+                syscall(PROCESS_WAIT),
+                // ADDU $t0, $t0, $v1
+                T0 << 21 | V1 << 16 | T0 << 11 | 0b100001,
+            ],
+            exit_with(T0).to_vec(),
+            // the child sleeps so the parent is already waiting when it exits
+            li(A0, 10_000_000).to_vec(),
+            vec![addiu(A1, 0, 0), syscall(SLEEP_NANOS), addiu(T0, 0, 7)],
+            exit_with(T0).to_vec(),
+        ]
+        .concat();
+
+        let data: Vec<u8> = code.iter().flat_map(|op| op.to_le_bytes()).collect();
+        let mut sys = System::builder().build();
+        let pid = sys.add_program(&Program::from_raw(&data)).to_pid();
+        let mut report = sys.run_blocking();
+        let parent = report
+            .processes
+            .iter()
+            .position(|process| process.pid == pid);
+        assert_eq!(
+            exit_code(report.processes.swap_remove(parent.unwrap()).status),
+            7 + 2
+        );
+        assert_eq!(exit_code(report.processes.remove(0).status), 7);
+    }
+
+    #[test]
+    fn address_space_replaces_and_unmaps_by_page() {
+        let mut space = AddressSpace::default();
+        let (first, second) = (Arc::new(Page::new()), Arc::new(Page::new()));
+        space.map(first.clone(), 5, Protection::READ);
+        space.map(second.clone(), 5, Protection::READ_WRITE);
+        space.map(first.clone(), 2, Protection::READ);
+
+        assert!(std::ptr::eq::<Page>(&**space.page(5).unwrap(), &*second));
+        assert_eq!(space.protection(5), Some(Protection::READ_WRITE));
+        assert_eq!(
+            space
+                .mappings()
+                .map(|(v_page, _, _)| v_page)
+                .collect::<Vec<_>>(),
+            [2, 5]
+        );
+        assert!(space.protect(2, Protection::ALL));
+        assert!(!space.protect(3, Protection::ALL));
+        assert!(space.unmap(5).is_some());
+        assert!(space.unmap(5).is_none());
+        assert_eq!(space.find_free(2), Some(3));
+    }
+
+    #[test]
+    fn address_space_generation_follows_every_change() {
+        let mut space = AddressSpace::default();
+        let other = AddressSpace::default();
+        assert_ne!(space.generation(), other.generation());
+
+        let mut seen = vec![space.generation()];
+        let mut changed = |space: &AddressSpace| {
+            assert!(!seen.contains(&space.generation()));
+            seen.push(space.generation());
+        };
+        space.map(Arc::new(Page::new()), 1, Protection::READ);
+        changed(&space);
+        space.protect(1, Protection::READ_WRITE);
+        changed(&space);
+        space.unmap(1);
+        changed(&space);
+
+        // nothing to unmap or protect leaves the generation alone
+        let generation = space.generation();
+        space.unmap(1);
+        space.protect(1, Protection::READ);
+        assert_eq!(space.generation(), generation);
+    }
+}
@@ -0,0 +1,113 @@
+//! Debug tracking of which words of memory were ever written, to catch reads of memory that only
+//! holds whatever the page was filled with
+
+use std::{collections::HashMap, sync::Mutex};
+
+use crate::util::{page_offset, Page, TaskId, PAGE_SIZE};
+
+use super::{VmInstructionAddress, VmPtr};
+
+const WORDS: usize = (PAGE_SIZE >> 2) as usize;
+
+/// One bit for every word of a page
+type WrittenWords = [u32; WORDS / 32];
+
+/// A load of a word that was never written
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct UninitializedRead {
+    pub tid: TaskId,
+    pub address: VmPtr,
+    pub pc: VmInstructionAddress,
+}
+
+/// Remembers the written words of every page by the pages host address. Pages the tracker
+/// hasn't seen count as never written
+#[derive(Default)]
+pub struct WriteTracker {
+    pages: Mutex<HashMap<usize, WrittenWords>>,
+    reads: Mutex<Vec<UninitializedRead>>,
+}
+
+fn key(page: &Page) -> usize {
+    page as *const Page as usize
+}
+
+fn word(address: VmPtr) -> (usize, u32) {
+    let word = (page_offset(address) >> 2) as usize;
+    (word / 32, 1 << (word % 32))
+}
+
+impl WriteTracker {
+    /// Marks the word containing `address` in `page` as written
+    pub fn mark(&self, page: &Page, address: VmPtr) {
+        let (index, bit) = word(address);
+        let mut pages = self.pages.lock().unwrap();
+        pages.entry(key(page)).or_insert([0; WORDS / 32])[index] |= bit;
+    }
+
+    /// Marks every word `len` bytes starting at `address` in `page` touch as written, they have
+    /// to lie inside the page
+    pub fn mark_range(&self, page: &Page, address: VmPtr, len: usize) {
+        if len == 0 {
+            return;
+        }
+        let first = (page_offset(address) >> 2) as usize;
+        let last = ((page_offset(address) as usize + len - 1) >> 2).min(WORDS - 1);
+        let mut pages = self.pages.lock().unwrap();
+        let words = pages.entry(key(page)).or_insert([0; WORDS / 32]);
+        for word in first..=last {
+            words[word / 32] |= 1 << (word % 32);
+        }
+    }
+
+    /// Marks every word of `page` as written
+    pub fn mark_page(&self, page: &Page) {
+        self.pages
+            .lock()
+            .unwrap()
+            .insert(key(page), [u32::MAX; WORDS / 32]);
+    }
+
+    /// Forgets every write to `page`, for when it is handed out again
+    pub fn forget(&self, page: &Page) {
+        self.pages.lock().unwrap().remove(&key(page));
+    }
+
+    /// Gives `to` the same written words as `from`
+    pub fn copy(&self, from: &Page, to: &Page) {
+        let mut pages = self.pages.lock().unwrap();
+        match pages.get(&key(from)).copied() {
+            Some(words) => pages.insert(key(to), words),
+            None => pages.remove(&key(to)),
+        };
+    }
+
+    /// Records a read of the word containing `address` by `tid` at `pc` if it was never written.
+    /// Each word is only reported once, after that it counts as written
+    pub fn check_read(&self, page: &Page, address: VmPtr, tid: TaskId, pc: VmInstructionAddress) {
+        let (index, bit) = word(address);
+        let mut pages = self.pages.lock().unwrap();
+        let words = pages.entry(key(page)).or_insert([0; WORDS / 32]);
+        if words[index] & bit != 0 {
+            return;
+        }
+        words[index] |= bit;
+        drop(pages);
+
+        tracing::warn!(
+            "Task: {} read uninitialized memory at {:#010X} from pc: {:#010X}",
+            tid,
+            address,
+            pc
+        );
+        self.reads
+            .lock()
+            .unwrap()
+            .push(UninitializedRead { tid, address, pc });
+    }
+
+    /// Every uninitialized read recorded since the last call
+    pub fn take_reads(&self) -> Vec<UninitializedRead> {
+        std::mem::take(&mut self.reads.lock().unwrap())
+    }
+}
@@ -1,14 +1,15 @@
 use std::{
-    collections::HashMap,
+    collections::{HashMap, HashSet},
     fmt::Display,
-    sync::{atomic::AtomicBool, Mutex, RwLock},
+    sync::{Mutex, RwLock},
 };
 
 use rclite::Arc;
 
 use crate::{
-    task::{Task, TaskMemory},
-    util::{Page, TaskId},
+    task::{Task, TaskMemory, VmPtr, WriteTracker},
+    util::{Page, ProcessId, TaskId},
+    SystemTime,
 };
 
 #[derive(Default)]
@@ -29,6 +30,14 @@ impl TaskPool {
     pub fn remove_task(&mut self, tid: TaskId) -> Arc<Mutex<Task>> {
         self.task_pool.remove(&tid).unwrap()
     }
+
+    pub fn process_tasks(&self, pid: ProcessId) -> Vec<TaskId> {
+        self.task_pool
+            .iter()
+            .filter(|(_, task)| task.lock().unwrap().thread_id().1 == pid)
+            .map(|(tid, _)| *tid)
+            .collect()
+    }
 }
 
 #[derive(Copy, Clone, Debug, Hash, PartialEq, Eq)]
@@ -45,51 +54,350 @@ impl Display for PageId {
     }
 }
 
+/// What the words of a page handed out by the page pool hold before they are first written
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub enum FillPolicy {
+    /// Pages start out zeroed, so memory like .bss needs no clearing
+    Zero,
+    /// Every word holds [`Page::UNINITIALIZED`], which makes reads of uninitialized memory stand
+    /// out
+    #[default]
+    DebugPattern,
+    /// Pseudo random words from a generator started with the given seed, the same seed and
+    /// program always give the same memory
+    Random(u64),
+}
+
 #[derive(Default)]
 pub struct TaskPoolSharedMemory {
-    pub v_mem: RwLock<Vec<(Arc<Page>, PageMetaData)>>,
-    pub ll_bit: AtomicBool,
+    pub v_mem: RwLock<PagePool>,
+    fill: FillPolicy,
+    /// Set when reads of never written memory should be reported
+    pub written: Option<Arc<WriteTracker>>,
+}
+
+/// Every page the system has ever created, pages no longer mapped anywhere are kept on a free
+/// list to be handed out again
+#[derive(Default)]
+pub struct PagePool {
+    pub pages: Vec<(Arc<Page>, PageMetaData)>,
+    /// The id of every page by its host address, pages never leave the pool so the addresses
+    /// stay valid
+    ids: HashMap<usize, PageId>,
+    free: Vec<PageId>,
+    /// Number of pages every process is an owner of
+    owned: HashMap<ProcessId, usize>,
+    /// State of the generator for [`FillPolicy::Random`]
+    random: u64,
+}
+
+impl PagePool {
+    pub fn page(&self, id: PageId) -> Option<&(Arc<Page>, PageMetaData)> {
+        self.pages.get(id.raw())
+    }
+
+    /// The id the page at `page` was created with, `None` for pages that aren't from this pool
+    pub fn id_of(&self, page: &Page) -> Option<PageId> {
+        self.ids.get(&(page as *const Page as usize)).copied()
+    }
+
+    /// How many mappings currently hold the page, not counting the pool itself
+    pub fn references(&self, id: PageId) -> usize {
+        self.page(id).map_or(0, |(page, _)| page.strong_count() - 1)
+    }
+
+    pub fn free_pages(&self) -> usize {
+        self.free.len()
+    }
+
+    /// Number of pages currently handed out to `pid`
+    pub fn process_pages(&self, pid: ProcessId) -> usize {
+        self.owned.get(&pid).copied().unwrap_or(0)
+    }
+
+    fn add_owned(&mut self, pid: ProcessId) {
+        *self.owned.entry(pid).or_insert(0) += 1;
+    }
+
+    fn remove_owned(&mut self, pid: ProcessId) {
+        if let Some(owned) = self.owned.get_mut(&pid) {
+            *owned -= 1;
+            if *owned == 0 {
+                self.owned.remove(&pid);
+            }
+        }
+    }
+
+    fn next_random(&mut self) -> u32 {
+        // splitmix64, which is fine with any seed
+        self.random = self.random.wrapping_add(0x9E3779B97F4A7C15);
+        let mut z = self.random;
+        z = (z ^ (z >> 30)).wrapping_mul(0xBF58476D1CE4E5B9);
+        z = (z ^ (z >> 27)).wrapping_mul(0x94D049BB133111EB);
+        (z ^ (z >> 31)) as u32
+    }
+
+    /// Moves the page onto the free list if it isn't mapped anywhere anymore, returns whether it
+    /// was freed
+    fn free_page(&mut self, id: PageId) -> bool {
+        let (page, meta) = &mut self.pages[id.raw()];
+        if meta.allocated.is_none() || page.strong_count() != 1 {
+            return false;
+        }
+        meta.allocated = None;
+        for owner in std::mem::take(&mut meta.owners) {
+            self.remove_owned(owner);
+        }
+        self.free.push(id);
+        true
+    }
+
+    /// Moves every page that isn't mapped anywhere anymore onto the free list
+    fn reclaim(&mut self) -> usize {
+        (0..self.pages.len())
+            .filter(|index| self.free_page(PageId(*index)))
+            .count()
+    }
 }
 
 impl TaskPoolSharedMemory {
-    pub fn new_page(&self) -> Arc<Page> {
-        let mut writter = self.v_mem.write().unwrap();
-        for (page_id, meta) in writter.iter_mut().enumerate() {
-            if meta.0.strong_count() == 1 {
-                meta.0 = Arc::new(Page::new());
-                return meta.0.clone();
+    pub fn new(fill: FillPolicy, track_writes: bool) -> Self {
+        let random = match fill {
+            FillPolicy::Random(seed) => seed,
+            _ => 0,
+        };
+        Self {
+            v_mem: RwLock::new(PagePool {
+                random,
+                ..Default::default()
+            }),
+            fill,
+            written: track_writes.then(|| Arc::new(WriteTracker::default())),
+        }
+    }
+
+    pub fn fill_policy(&self) -> FillPolicy {
+        self.fill
+    }
+
+    /// Hands out a page owned by `owner`, reusing a free page if there is one. The page is filled
+    /// according to the fill policy
+    pub fn new_page(&self, owner: ProcessId) -> Arc<Page> {
+        let mut pool = self.v_mem.write().unwrap();
+        let meta = PageMetaData {
+            owners: vec![owner],
+            allocated: Some(crate::systime_now()),
+        };
+        pool.add_owned(owner);
+
+        let page = match pool.free.pop() {
+            Some(id) => {
+                let (page, old) = &mut pool.pages[id.raw()];
+                *old = meta;
+                let page = page.clone();
+                if self.fill == FillPolicy::DebugPattern {
+                    page.fill(Page::UNINITIALIZED);
+                }
+                page
+            }
+            None => {
+                let page = Arc::new(Page::new());
+                let id = PageId(pool.pages.len());
+                pool.ids.insert(&*page as *const Page as usize, id);
+                pool.pages.push((page.clone(), meta));
+                page
             }
+        };
+        match self.fill {
+            FillPolicy::Zero => page.fill(0),
+            FillPolicy::DebugPattern => {}
+            FillPolicy::Random(_) => page.fill_with(|| pool.next_random()),
         }
-        let new = Arc::new(Page::new());
-        writter.push((new.clone(), PageMetaData {}));
-        new
+        if let Some(written) = &self.written {
+            written.forget(&page);
+        }
+        page
     }
 
-    pub fn task_with_mapping<R, F>(
-        &self,
-        task: &mut Task,
-        mem: &mut TaskMemory<'_, '_>,
-        scope: F,
-    ) -> R
-    where
-        F: for<'f> FnOnce(&mut Task, &mut TaskMemory<'_, 'f>) -> R,
-    {
-        for (page_id, v_addr) in &task.memory_mapping.mapping {
-            //extend the lifetime
-            let page = unsafe { std::mem::transmute::<&Page, &Page>(&**page_id) };
-            mem.mem[*v_addr as usize] = Some(page);
+    /// Hands out a zeroed page owned by `owner`, all of it counts as written
+    pub fn new_zeroed_page(&self, owner: ProcessId) -> Arc<Page> {
+        let page = self.new_page(owner);
+        if self.fill != FillPolicy::Zero {
+            page.fill(0);
+        }
+        if let Some(written) = &self.written {
+            written.mark_page(&page);
+        }
+        page
+    }
+
+    /// Marks the words `len` bytes starting at `address` in `page` touch as written, for memory
+    /// the host fills in
+    pub fn mark_written(&self, page: &Page, address: VmPtr, len: usize) {
+        if let Some(written) = &self.written {
+            written.mark_range(page, address, len);
+        }
+    }
+
+    /// Hands out a copy of `page` owned by `owner`, who no longer owns the original
+    pub fn copy_page(&self, page: &Page, owner: ProcessId) -> Arc<Page> {
+        let copy = self.new_page(owner);
+        copy.copy_from(page);
+        if let Some(written) = &self.written {
+            written.copy(page, &copy);
         }
 
-        let res = scope(task, mem);
+        let mut pool = self.v_mem.write().unwrap();
+        if let Some(id) = pool.id_of(page) {
+            let meta = &mut pool.pages[id.raw()].1;
+            if let Some(index) = meta.owners.iter().position(|other| *other == owner) {
+                meta.owners.swap_remove(index);
+                pool.remove_owned(owner);
+            }
+        }
+        copy
+    }
 
-        // make sure that after extending the lifetime we MUST remove all the references we placed into here ( or things break badly :) )
-        for (_, v_addr) in &task.memory_mapping.mapping {
-            mem.mem[*v_addr as usize] = None;
+    /// Adds `owner` to the owners of every page in `pages`
+    pub fn share_pages<'p>(&self, pages: impl IntoIterator<Item = &'p Page>, owner: ProcessId) {
+        let mut pool = self.v_mem.write().unwrap();
+        let ids: HashSet<PageId> = pages
+            .into_iter()
+            .filter_map(|page| pool.id_of(page))
+            .collect();
+        let mut shared = 0;
+        for id in ids {
+            let meta = &mut pool.pages[id.raw()].1;
+            if !meta.owners.contains(&owner) {
+                meta.owners.push(owner);
+                shared += 1;
+            }
+        }
+        if shared > 0 {
+            *pool.owned.entry(owner).or_insert(0) += shared;
         }
+    }
+
+    /// Puts pages that were unmapped or belonged to tasks that are gone back on the free list,
+    /// returns how many pages were freed
+    pub fn reclaim(&self) -> usize {
+        self.v_mem.write().unwrap().reclaim()
+    }
 
-        res
-        // todo!();
+    /// Takes the pages a mapping just let go of and puts the ones nothing else maps back on the
+    /// free list, without looking at the rest of the pool. Returns how many pages were freed
+    pub fn reclaim_pages(&self, pages: impl IntoIterator<Item = Arc<Page>>) -> usize {
+        let mut pool = self.v_mem.write().unwrap();
+        // each page is dropped right after its lookup, leaving the pool with the only reference
+        // to the ones that are unused now
+        let ids: Vec<PageId> = pages
+            .into_iter()
+            .filter_map(|page| pool.id_of(&page))
+            .collect();
+        ids.into_iter().filter(|id| pool.free_page(*id)).count()
     }
+
+    /// Drops `pid` from the owners of every page and frees whatever it no longer uses
+    pub fn release_process(&self, pid: ProcessId) -> usize {
+        let mut pool = self.v_mem.write().unwrap();
+        for (_, meta) in &mut pool.pages {
+            meta.owners.retain(|owner| *owner != pid);
+        }
+        pool.owned.remove(&pid);
+        pool.reclaim()
+    }
+
+    pub fn task_with_mapping<R, F>(&self, task: &mut Task, mem: &mut TaskMemory<'_>, scope: F) -> R
+    where
+        F: for<'f> FnOnce(&mut Task, &mut TaskMemory<'f>) -> R,
+    {
+        let shared = task.memory_mapping.address_space.clone();
+        let shared = shared.lock().unwrap();
+        let private = &task.memory_mapping.private;
+        let generations = (shared.generation(), private.generation());
+        // a task that runs again without its mapping changing in between finds everything in
+        // place, the pages stay alive as the pool never drops a page
+        if mem.mapped_generations != Some(generations) {
+            mem.unmap_all();
+            // private pages go last so they win over shared pages at the same address
+            for (v_page, page, protection) in shared.mappings().chain(private.mappings()) {
+                unsafe { mem.map_page(page, v_page, protection) };
+            }
+            // any change made while the task runs moves the generations on
+            mem.mapped_generations = Some(generations);
+        }
+        drop(shared);
+        // a reservation never survives a context switch
+        mem.ll_reservation = 0;
+
+        scope(task, mem)
+    }
+}
+
+#[derive(Debug, Default)]
+pub struct PageMetaData {
+    /// Processes the page was handed to
+    pub owners: Vec<ProcessId>,
+    /// When the page was handed out, `None` while it sits on the free list
+    pub allocated: Option<SystemTime>,
 }
 
-pub struct PageMetaData {}
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn copy_page_moves_ownership_to_the_copy() {
+        let mem = TaskPoolSharedMemory::new(FillPolicy::Zero, false);
+        let (parent, child) = (ProcessId::from_raw(1), ProcessId::from_raw(2));
+        let page = mem.new_page(parent);
+        page.write_bytes(8, &[1, 2, 3, 4]);
+        mem.share_pages([&*page], child);
+        // sharing twice doesn't count the page twice
+        mem.share_pages([&*page], child);
+        assert_eq!(mem.v_mem.read().unwrap().process_pages(child), 1);
+
+        let copy = mem.copy_page(&page, child);
+        assert_eq!([8, 9, 10, 11].map(|i| copy.get_u8(i)), [1, 2, 3, 4]);
+        let pool = mem.v_mem.read().unwrap();
+        assert_eq!(pool.process_pages(parent), 1);
+        assert_eq!(pool.process_pages(child), 1);
+        let id = pool.id_of(&page).unwrap();
+        assert_eq!(pool.page(id).unwrap().1.owners, [parent]);
+        assert_eq!(
+            pool.page(pool.id_of(&copy).unwrap()).unwrap().1.owners,
+            [child]
+        );
+    }
+
+    #[test]
+    fn reclaimed_pages_keep_their_id() {
+        let mem = TaskPoolSharedMemory::new(FillPolicy::Zero, false);
+        let pid = ProcessId::from_raw(1);
+        let page = mem.new_page(pid);
+        let id = mem.v_mem.read().unwrap().id_of(&page).unwrap();
+        drop(page);
+        assert_eq!(mem.reclaim(), 1);
+        let page = mem.new_page(pid);
+        assert_eq!(mem.v_mem.read().unwrap().id_of(&page), Some(id));
+        assert_eq!(mem.v_mem.read().unwrap().id_of(&Page::new()), None);
+    }
+
+    #[test]
+    fn reclaim_pages_only_frees_the_given_unused_pages() {
+        let mem = TaskPoolSharedMemory::new(FillPolicy::Zero, false);
+        let pid = ProcessId::from_raw(1);
+        let (kept, shared, other) = (mem.new_page(pid), mem.new_page(pid), mem.new_page(pid));
+        let mapped_elsewhere = shared.clone();
+        drop(other);
+
+        assert_eq!(mem.reclaim_pages([kept.clone(), shared]), 0);
+        assert_eq!(mem.reclaim_pages([kept.clone(), kept]), 1);
+        // pages nobody handed back stay where they are until a full reclaim
+        assert_eq!(mem.v_mem.read().unwrap().free_pages(), 1);
+        assert_eq!(mem.v_mem.read().unwrap().process_pages(pid), 2);
+        drop(mapped_elsewhere);
+        assert_eq!(mem.reclaim(), 2);
+        assert_eq!(mem.v_mem.read().unwrap().process_pages(pid), 0);
+    }
+}
@@ -30,7 +30,7 @@ impl ProcessId {
 #[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord, Hash)]
 pub struct TaskId(NonZeroU32);
 
-impl PartialEq<ThreadId> for TaskId{
+impl PartialEq<ThreadId> for TaskId {
     fn eq(&self, other: &ThreadId) -> bool {
         *self == other.0
     }
@@ -73,9 +73,43 @@ impl PartialEq<TaskId> for ProcessId {
 
 pub type ThreadId = (TaskId, ProcessId);
 
+/// Pages are `1 << PAGE_SHIFT` bytes, everything else about pages follows from this. Offsets
+/// inside a page are u16 so pages can be at most 64 KiB
+pub const PAGE_SHIFT: u32 = 12;
+pub const PAGE_SIZE: u32 = 1 << PAGE_SHIFT;
+/// The bits of an address that select a byte inside its page
+pub const PAGE_OFFSET_MASK: u32 = PAGE_SIZE - 1;
+/// Number of pages in the 32 bit address space
+pub const PAGE_COUNT: usize = 1 << (32 - PAGE_SHIFT);
+
+const _: () = assert!(PAGE_SHIFT >= 2 && PAGE_SHIFT <= 16);
+
+/// The page `address` lies in
+#[inline(always)]
+pub const fn page_number(address: u32) -> u32 {
+    address >> PAGE_SHIFT
+}
+
+/// Where `address` lies inside its page
+#[inline(always)]
+pub const fn page_offset(address: u32) -> u16 {
+    (address & PAGE_OFFSET_MASK) as u16
+}
+
+/// The first address of `page`
+#[inline(always)]
+pub const fn page_address(page: u32) -> u32 {
+    page << PAGE_SHIFT
+}
+
+/// How many pages it takes to hold `bytes` bytes
+pub const fn pages_for(bytes: u64) -> u64 {
+    (bytes + PAGE_OFFSET_MASK as u64) >> PAGE_SHIFT
+}
+
 #[derive(Debug)]
-#[repr(align(0x10000))]
-pub struct Page([AtomicU32; 0x10000 >> 2]);
+#[repr(align(0x1000))]
+pub struct Page([AtomicU32; (PAGE_SIZE >> 2) as usize]);
 
 use std::sync::atomic::Ordering::Relaxed;
 
@@ -106,13 +140,13 @@ macro_rules! core_atomic {
 
         impl Page {
             #[inline(always)]
-            /// # Safety `index` must always be properly aligned
+            /// # Safety `index` must always be properly aligned and inside the page
             pub unsafe fn $get_name(&self, index: u16) -> $reg {
                 self.load_from_core_unchecked::<$reg>(index)
             }
 
             #[inline(always)]
-            /// # Safety `index` must always be properly aligned
+            /// # Safety `index` must always be properly aligned and inside the page
             pub unsafe fn $set_name(&self, index: u16, val: $reg) {
                 self.set_from_core_unchecked::<$reg>(index, val)
             }
@@ -138,12 +172,12 @@ macro_rules! core_atomic {
         impl Page {
             #[inline(always)]
             pub fn $get_name(&self, index: u16) -> $reg {
-                unsafe { self.load_from_core_unchecked::<$reg>(index) }
+                unsafe { self.load_from_core_unchecked::<$reg>(page_offset(index as u32)) }
             }
 
             #[inline(always)]
             pub fn $set_name(&self, index: u16, val: $reg) {
-                unsafe { self.set_from_core_unchecked::<$reg>(index, val) }
+                unsafe { self.set_from_core_unchecked::<$reg>(page_offset(index as u32), val) }
             }
         }
     };
@@ -163,10 +197,71 @@ impl Default for Page {
 }
 
 impl Page {
+    /// What every word of a page holds before it is first written
+    pub const UNINITIALIZED: u32 = 0xdbdbdbdb;
+
     pub fn new() -> Page {
-        Page(unsafe { std::mem::transmute([0xdbdbdbdbu32; 0x10000 >> 2]) })
+        Page(unsafe { std::mem::transmute([Self::UNINITIALIZED; (PAGE_SIZE >> 2) as usize]) })
+    }
+
+    /// Overwrites this page with the contents of `other`
+    pub fn copy_from(&self, other: &Page) {
+        for (word, other) in self.0.iter().zip(&other.0) {
+            word.store(other.load(Relaxed), Relaxed);
+        }
+    }
+
+    /// Sets every word of the page to `val`
+    pub fn fill(&self, val: u32) {
+        self.fill_with(|| val)
+    }
+
+    /// Sets every word of the page to the next value `f` returns
+    pub fn fill_with(&self, mut f: impl FnMut() -> u32) {
+        for word in &self.0 {
+            word.store(f(), Relaxed);
+        }
+    }
+
+    /// Copies `data` into the page starting at `offset`, it has to fit inside the page
+    pub fn write_bytes(&self, offset: u16, data: &[u8]) {
+        self.store_bytes(offset, data.len(), |index| data[index]);
     }
 
+    /// Sets `len` bytes starting at `offset` to `val`, they have to fit inside the page
+    pub fn fill_bytes(&self, offset: u16, len: usize, val: u8) {
+        self.store_bytes(offset, len, |_| val);
+    }
+
+    /// Stores `byte(i)` at `offset + i` for every `i` below `len`, whole words at a time where
+    /// the range covers them
+    fn store_bytes(&self, offset: u16, len: usize, byte: impl Fn(usize) -> u8) {
+        let start = offset as usize;
+        assert!(start + len <= PAGE_SIZE as usize);
+        let words_start = (start + 3) & !3;
+        let words_end = ((start + len) & !3).max(words_start);
+        let unaligned =
+            (start..words_start.min(start + len)).chain(words_end.max(start)..start + len);
+        for address in unaligned {
+            self.set_u8(address as u16, byte(address - start));
+        }
+        for (word, address) in self.0[words_start >> 2..words_end >> 2]
+            .iter()
+            .zip((words_start..).step_by(4))
+        {
+            let index = address - start;
+            let bytes = [
+                byte(index),
+                byte(index + 1),
+                byte(index + 2),
+                byte(index + 3),
+            ];
+            word.store(u32::from_ne_bytes(bytes), Relaxed);
+        }
+    }
+
+    // `index` has to be inside the page and aligned for `T`
+
     #[inline(always)]
     pub unsafe fn set_from_core_unchecked<T: CoreAtomic>(&self, index: u16, val: T::Regular) {
         T::store_atomic(
@@ -202,3 +297,45 @@ impl Page {
             .unwrap_unchecked()
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn bytes(page: &Page, offset: u16, len: usize) -> Vec<u8> {
+        (0..len).map(|i| page.get_u8(offset + i as u16)).collect()
+    }
+
+    #[test]
+    fn write_bytes_matches_byte_stores() {
+        let data = (1..=23).collect::<Vec<u8>>();
+        for offset in 0..8 {
+            for len in 0..data.len() {
+                let page = Page::new();
+                page.write_bytes(offset, &data[..len]);
+                assert_eq!(bytes(&page, offset, len), &data[..len]);
+                // the bytes around the range are left alone
+                if offset > 0 {
+                    assert_eq!(page.get_u8(offset - 1), 0xdb);
+                }
+                assert_eq!(page.get_u8(offset + len as u16), 0xdb);
+            }
+        }
+    }
+
+    #[test]
+    fn fill_bytes_reaches_the_end_of_the_page() {
+        let page = Page::new();
+        page.fill_bytes(3, PAGE_SIZE as usize - 3, 0);
+        assert_eq!(bytes(&page, 0, 3), [0xdb; 3]);
+        assert!(bytes(&page, 3, PAGE_SIZE as usize - 3)
+            .iter()
+            .all(|byte| *byte == 0));
+    }
+
+    #[test]
+    #[should_panic]
+    fn write_bytes_past_the_page_panics() {
+        Page::new().write_bytes(PAGE_SIZE as u16 - 2, &[1, 2, 3]);
+    }
+}